@@ -0,0 +1,208 @@
+// Command interop-vectors writes the testdata/interop-*.json vectors the Rust tests check against:
+// a secp256k1 and an RSA key in go-libp2p's private key encoding, with an advertisement and a
+// legacy signed head signed by each, encoded by go-ipld-prime.
+//
+// Run it from the importer-helper directory:
+//
+//	go run ./cmd/interop-vectors -out ../testdata
+//
+// A vector file that already exists keeps its key, so only the signatures can change.
+//
+// There is no ECDSA vector: the Rust side builds on libp2p 0.41, which has no ECDSA key type, so
+// ECDSA is out of scope until it moves to 0.42 or later. Add crypto.ECDSA to the list below then.
+package main
+
+import (
+	"bytes"
+	"crypto/sha256"
+	"encoding/base64"
+	"encoding/binary"
+	"encoding/json"
+	"flag"
+	"fmt"
+	"os"
+	"path/filepath"
+
+	"github.com/ipfs/go-cid"
+	"github.com/ipld/go-ipld-prime/codec/dagcbor"
+	"github.com/ipld/go-ipld-prime/codec/dagjson"
+	"github.com/ipld/go-ipld-prime/datamodel"
+	cidlink "github.com/ipld/go-ipld-prime/linking/cid"
+	"github.com/ipld/go-ipld-prime/node/basicnode"
+	"github.com/libp2p/go-libp2p-core/crypto"
+	"github.com/libp2p/go-libp2p-core/peer"
+	"github.com/libp2p/go-libp2p-core/record"
+	"github.com/multiformats/go-multihash"
+)
+
+// The head every vector signs.
+const headCid = "bafybeicyhbhhklw3kdwgrxmf67mhkgjbsjauphsvrzywav63kn7bkpmqfa"
+
+// An arbitrary protocol id for the vectors' metadata.
+const metadataProtocol = 0x10000
+
+type keyFile struct {
+	Current string `json:"current"`
+}
+
+type vector struct {
+	PeerID     string  `json:"peer_id"`
+	KeyFile    keyFile `json:"key_file"`
+	SignedHead string  `json:"signed_head"`
+	Ad         string  `json:"ad"`
+}
+
+// adSignature is the record storetheindex seals an advertisement's signature payload in.
+type adSignature struct {
+	payload []byte
+}
+
+func (r *adSignature) Domain() string                 { return "indexer" }
+func (r *adSignature) Codec() []byte                  { return []byte("/indexer/ingest/adSignature") }
+func (r *adSignature) MarshalRecord() ([]byte, error) { return r.payload, nil }
+func (r *adSignature) UnmarshalRecord(b []byte) error { r.payload = b; return nil }
+
+func noErr(err error) {
+	if err != nil {
+		panic("Unexpected error: " + err.Error())
+	}
+}
+
+func main() {
+	out := flag.String("out", "../testdata", "directory to write the vectors to")
+	flag.Parse()
+
+	for _, keyType := range []struct {
+		name string
+		typ  int
+		bits int
+	}{
+		{"secp256k1", crypto.Secp256k1, 0},
+		{"rsa", crypto.RSA, 2048},
+	} {
+		path := filepath.Join(*out, fmt.Sprintf("interop-%s.json", keyType.name))
+		key := loadKey(path)
+		if key == nil {
+			var err error
+			key, _, err = crypto.GenerateKeyPair(keyType.typ, keyType.bits)
+			noErr(err)
+		}
+		v := makeVector(key)
+		data, err := json.MarshalIndent(v, "", "  ")
+		noErr(err)
+		noErr(os.WriteFile(path, append(data, '\n'), 0o644))
+		fmt.Println("Wrote", path, v.PeerID)
+	}
+}
+
+// loadKey returns the key of an existing vector, or nil if there is none.
+func loadKey(path string) crypto.PrivKey {
+	data, err := os.ReadFile(path)
+	if os.IsNotExist(err) {
+		return nil
+	}
+	noErr(err)
+	var v vector
+	noErr(json.Unmarshal(data, &v))
+	raw, err := base64.StdEncoding.DecodeString(v.KeyFile.Current)
+	noErr(err)
+	key, err := crypto.UnmarshalPrivateKey(raw)
+	noErr(err)
+	return key
+}
+
+func makeVector(key crypto.PrivKey) vector {
+	id, err := peer.IDFromPrivateKey(key)
+	noErr(err)
+	raw, err := crypto.MarshalPrivateKey(key)
+	noErr(err)
+	return vector{
+		PeerID:     id.Pretty(),
+		KeyFile:    keyFile{Current: base64.StdEncoding.EncodeToString(raw)},
+		SignedHead: signedHead(key),
+		Ad:         base64.StdEncoding.EncodeToString(ad(key, id)),
+	}
+}
+
+// signedHead signs the cid's bytes, and encodes the head as dag-json.
+func signedHead(key crypto.PrivKey) string {
+	head, err := cid.Decode(headCid)
+	noErr(err)
+	sig, err := key.Sign(head.Bytes())
+	noErr(err)
+	pubkey, err := crypto.MarshalPublicKey(key.GetPublic())
+	noErr(err)
+
+	nb := basicnode.Prototype.Map.NewBuilder()
+	ma, err := nb.BeginMap(3)
+	noErr(err)
+	// Keys in dag-json's sorted order.
+	noErr(ma.AssembleKey().AssignString("head"))
+	noErr(ma.AssembleValue().AssignLink(cidlink.Link{Cid: head}))
+	noErr(ma.AssembleKey().AssignString("pubkey"))
+	noErr(ma.AssembleValue().AssignBytes(pubkey))
+	noErr(ma.AssembleKey().AssignString("sig"))
+	noErr(ma.AssembleValue().AssignBytes(sig))
+	noErr(ma.Finish())
+
+	var buf bytes.Buffer
+	noErr(dagjson.Encode(nb.Build(), &buf))
+	return buf.String()
+}
+
+// ad builds an advertisement with no previous ad and no entries, signed the way storetheindex
+// signs them, and encodes it as dag-cbor.
+func ad(key crypto.PrivKey, id peer.ID) []byte {
+	provider := id.Pretty()
+	addrs := []string{"/ip4/127.0.0.1/tcp/9999"}
+	metadata := make([]byte, binary.MaxVarintLen64)
+	metadata = append(metadata[:binary.PutUvarint(metadata, metadataProtocol)], "interop"...)
+
+	// The signature covers the previous id and entries links, which are empty here, then the
+	// provider, addresses, metadata and removal flag.
+	payload := []byte(provider)
+	for _, addr := range addrs {
+		payload = append(payload, addr...)
+	}
+	payload = append(payload, metadata...)
+	payload = append(payload, 0)
+	digest := sha256.Sum256(payload)
+	mh, err := multihash.Encode(digest[:], multihash.SHA2_256)
+	noErr(err)
+	env, err := record.Seal(&adSignature{payload: mh}, key)
+	noErr(err)
+	sig, err := env.Marshal()
+	noErr(err)
+
+	nb := basicnode.Prototype.Map.NewBuilder()
+	ma, err := nb.BeginMap(7)
+	noErr(err)
+	// Keys in dag-cbor's canonical order: shortest first, then bytewise.
+	entry := func(k string, assign func(datamodel.NodeAssembler) error) {
+		noErr(ma.AssembleKey().AssignString(k))
+		noErr(assign(ma.AssembleValue()))
+	}
+	entry("IsRm", func(na datamodel.NodeAssembler) error { return na.AssignBool(false) })
+	entry("Entries", func(na datamodel.NodeAssembler) error { return na.AssignNull() })
+	entry("Metadata", func(na datamodel.NodeAssembler) error { return na.AssignBytes(metadata) })
+	entry("Provider", func(na datamodel.NodeAssembler) error { return na.AssignString(provider) })
+	entry("Addresses", func(na datamodel.NodeAssembler) error {
+		la, err := na.BeginList(int64(len(addrs)))
+		if err != nil {
+			return err
+		}
+		for _, addr := range addrs {
+			if err := la.AssembleValue().AssignString(addr); err != nil {
+				return err
+			}
+		}
+		return la.Finish()
+	})
+	entry("ContextID", func(na datamodel.NodeAssembler) error { return na.AssignBytes([]byte("interop")) })
+	entry("Signature", func(na datamodel.NodeAssembler) error { return na.AssignBytes(sig) })
+	noErr(ma.Finish())
+
+	var buf bytes.Buffer
+	noErr(dagcbor.Encode(nb.Build(), &buf))
+	return buf.Bytes()
+}
@@ -1,15 +1,14 @@
-use forest_cid;
+use crate::signer::{self, Signer, SignerError};
 use forest_ipld as ipld;
 use forest_ipld::Ipld;
 use ipld_blockstore::BlockStore;
 use libp2p::core::{signed_envelope, SignedEnvelope};
-use libp2p::identity::Keypair;
 use multihash::MultihashDigest;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-const AD_SIGNATURE_CODEC: &'static str = "/indexer/ingest/adSignature";
-const AD_SIGNATURE_DOMAIN: &'static str = "indexer";
+const AD_SIGNATURE_CODEC: &str = "/indexer/ingest/adSignature";
+const AD_SIGNATURE_DOMAIN: &str = "indexer";
 
 /// Represents the advertisement we are going to broadcast too the indexers.
 /// This is defined at: <https://github.com/filecoin-project/storetheindex/blob/main/api/v0/ingest/schema/schema.ipldsch>
@@ -36,14 +35,15 @@ pub struct Advertisement {
 }
 
 impl Advertisement {
-    fn sign(&self, signing_key: Keypair) -> Result<SignedEnvelope, AdSigError> {
-        Ok(SignedEnvelope::new(
-            signing_key,
-            AD_SIGNATURE_DOMAIN.into(),
-            AD_SIGNATURE_CODEC.into(),
-            self.sig_payload()?,
+    async fn sign(&self, signer: &dyn Signer) -> Result<Vec<u8>, AdSigError> {
+        signer::seal(
+            signer,
+            AD_SIGNATURE_DOMAIN,
+            AD_SIGNATURE_CODEC.as_bytes(),
+            &self.sig_payload()?,
         )
-        .map_err(AdSigError::SigningError)?)
+        .await
+        .map_err(AdSigError::SigningError)
     }
 
     pub fn sig_payload(&self) -> Result<Vec<u8>, AdSigError> {
@@ -78,10 +78,10 @@ impl Advertisement {
 
         payload.append(&mut previous_id_bytes);
         payload.append(&mut entrychunk_link_bytes);
-        payload.extend_from_slice(&self.Provider.as_bytes());
+        payload.extend_from_slice(self.Provider.as_bytes());
         self.Addresses
             .iter()
-            .for_each(|s| payload.extend_from_slice(&s.as_bytes()));
+            .for_each(|s| payload.extend_from_slice(s.as_bytes()));
         payload.extend_from_slice(metadata);
         payload.extend_from_slice(&is_rm_payload);
 
@@ -95,7 +95,7 @@ impl Advertisement {
             _ => return Err(AdSigError::MissingSig),
         };
 
-        let signed_env = SignedEnvelope::from_protobuf_encoding(&signed_env_bytes)
+        let signed_env = SignedEnvelope::from_protobuf_encoding(signed_env_bytes)
             .map_err(AdSigError::DecodingError)?;
 
         let signed_payload = signed_env
@@ -116,6 +116,9 @@ impl Advertisement {
 pub(crate) struct AdvertisementBuilder {
     pub(crate) ad: Advertisement,
     pub(crate) entries_link: Option<ipld::Ipld>,
+    /// Number of multihashes linked so far.
+    #[serde(default)]
+    pub(crate) entry_count: usize,
 }
 
 #[derive(Debug, Error)]
@@ -129,7 +132,7 @@ pub enum AdSigError {
     #[error("Missing Signature")]
     MissingSig,
     #[error("Failed to sign advertisement: {0}")]
-    SigningError(libp2p::identity::error::SigningError),
+    SigningError(SignerError),
     #[error("Failed to decode sig: {0}")]
     DecodingError(signed_envelope::DecodingError),
     #[error("Failed to read signed payload: {0}")]
@@ -144,14 +147,16 @@ impl AdvertisementBuilder {
         chunk_builder: &dyn EntryChunkBuilder,
         entries: Vec<Ipld>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let count = entries.len();
         self.entries_link = Some(chunk_builder.link_entries(self.entries_link.take(), entries)?);
+        self.entry_count += count;
         Ok(())
     }
 
-    pub(crate) fn build(mut self, signing_key: Keypair) -> Result<Advertisement, AdSigError> {
+    pub(crate) async fn build(mut self, signer: &dyn Signer) -> Result<Advertisement, AdSigError> {
         self.ad.Entries = self.entries_link;
-        let sig = self.ad.sign(signing_key)?;
-        self.ad.Signature = Ipld::Bytes(sig.into_protobuf_encoding());
+        let sig = self.ad.sign(signer).await?;
+        self.ad.Signature = Ipld::Bytes(sig);
         Ok(self.ad)
     }
 }
@@ -184,7 +189,7 @@ impl<BS: BlockStore> EntryChunkBuilder for BS {
             Next: entries_link,
         };
         let cid = self.put(&chunk, forest_cid::Code::Blake2b256)?;
-        return Ok(ipld::Ipld::Link(cid));
+        Ok(ipld::Ipld::Link(cid))
     }
 }
 
@@ -212,6 +217,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_adv_other_key_types() -> Result<(), Box<dyn std::error::Error>> {
+        // Signed with secp256k1 and RSA keys, see importer-helper/cmd/interop-vectors.
+        for vector in [
+            include_str!("../testdata/interop-secp256k1.json"),
+            include_str!("../testdata/interop-rsa.json"),
+        ] {
+            let vector: serde_json::Value = serde_json::from_str(vector)?;
+            let ad_bytes = base64::decode(vector["ad"].as_str().unwrap())?;
+            let ad: Advertisement = forest_encoding::from_slice(&ad_bytes)?;
+            ad.verify_sig()?;
+            assert_eq!(ad.Provider, vector["peer_id"]);
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_roundtrip_sig() {
         let bs = MemoryDB::default();
@@ -222,6 +243,7 @@ mod tests {
 
         let mut ad_builder = AdvertisementBuilder {
             entries_link: None,
+            entry_count: 0,
             ad: Advertisement {
                 Entries: None,
                 Signature: Ipld::Bytes(vec![]),
@@ -238,10 +260,35 @@ mod tests {
             .link_entries(&bs, vec![Ipld::Bytes(mh.into())])
             .unwrap();
 
-        let ad = ad_builder.build(keypair.clone()).expect("Signing failed");
+        let ad = async_std::task::block_on(ad_builder.build(&keypair)).expect("Signing failed");
         ad.verify_sig().expect("Signature verification failed");
     }
 
+    #[test]
+    fn test_remote_sig() {
+        async_std::task::block_on(async {
+            let keypair = libp2p::identity::Keypair::generate_ed25519();
+            let signer = crate::signer::tests::remote_signer(keypair.clone()).await;
+            let ad = Advertisement {
+                Entries: None,
+                Signature: Ipld::Bytes(vec![]),
+                Addresses: vec!["/ip4/1.1.1.1/tcp/1234".into()],
+                ContextID: Ipld::Bytes("asdf".into()),
+                IsRm: false,
+                Metadata: Ipld::Bytes("Some meta".into()),
+                PreviousID: None,
+                Provider: libp2p::PeerId::from_public_key(&keypair.public()).to_base58(),
+            };
+            let builder = AdvertisementBuilder {
+                entries_link: None,
+                entry_count: 0,
+                ad,
+            };
+            let ad = builder.build(&signer).await.expect("Signing failed");
+            ad.verify_sig().expect("Signature verification failed");
+        })
+    }
+
     #[test]
     fn test_build_entries() {
         let bs = MemoryDB::default();
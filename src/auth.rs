@@ -0,0 +1,151 @@
+//! Bearer token authentication for the admin app.
+use serde::Deserialize;
+use std::sync::Arc;
+use tide::http::headers::{AUTHORIZATION, WWW_AUTHENTICATE};
+use tide::{Middleware, Next, Request, StatusCode};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Create advertisements and add entries to them.
+    Create,
+    /// Publish advertisements, or import a chain.
+    Publish,
+    /// Create removal (`IsRm`) advertisements.
+    Remove,
+    /// Read-only admin routes, such as export and metrics.
+    Read,
+    /// Rotate the publisher key.
+    Keys,
+}
+
+const ALL_SCOPES: &[Scope] = &[
+    Scope::Create,
+    Scope::Publish,
+    Scope::Remove,
+    Scope::Read,
+    Scope::Keys,
+];
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub token: String,
+    /// Scopes granted to this token. All scopes when omitted.
+    #[serde(default)]
+    pub scopes: Option<Vec<Scope>>,
+}
+
+/// The scopes granted to the current request, set by `Auth`.
+#[derive(Clone, Debug)]
+struct Granted(Vec<Scope>);
+
+/// Middleware that rejects requests without a valid `Authorization: Bearer <token>` header.
+/// With no tokens configured, every request is granted every scope.
+#[derive(Clone, Debug)]
+pub struct Auth {
+    tokens: Arc<Vec<TokenConfig>>,
+}
+
+impl Auth {
+    pub fn new(tokens: Vec<TokenConfig>) -> Self {
+        Auth {
+            tokens: Arc::new(tokens),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    fn scopes_for(&self, token: &str) -> Option<Vec<Scope>> {
+        // Check every token, so the time taken doesn't depend on which one matched.
+        self.tokens
+            .iter()
+            .fold(None, |found, candidate| {
+                if constant_time_eq(candidate.token.as_bytes(), token.as_bytes()) {
+                    Some(candidate)
+                } else {
+                    found
+                }
+            })
+            .map(|t| t.scopes.clone().unwrap_or_else(|| ALL_SCOPES.to_vec()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn unauthorized(msg: &'static str) -> tide::Result {
+    let mut res = tide::Response::new(StatusCode::Unauthorized);
+    res.insert_header(WWW_AUTHENTICATE, "Bearer");
+    res.set_error(tide::Error::from_str(StatusCode::Unauthorized, msg));
+    Ok(res)
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Auth {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !self.is_enabled() {
+            req.set_ext(Granted(ALL_SCOPES.to_vec()));
+            return Ok(next.run(req).await);
+        }
+
+        let token = req
+            .header(AUTHORIZATION)
+            .and_then(|v| v.as_str().strip_prefix("Bearer "))
+            .map(|t| t.trim().to_string());
+        let token = match token {
+            Some(token) => token,
+            None => return unauthorized("Missing bearer token"),
+        };
+        match self.scopes_for(&token) {
+            Some(scopes) => {
+                req.set_ext(Granted(scopes));
+                Ok(next.run(req).await)
+            }
+            None => unauthorized("Invalid bearer token"),
+        }
+    }
+}
+
+/// Fails with 403 unless the request was granted the scope.
+pub fn require<State>(req: &Request<State>, scope: Scope) -> tide::Result<()> {
+    match req.ext::<Granted>() {
+        Some(Granted(scopes)) if scopes.contains(&scope) => Ok(()),
+        Some(_) => Err(tide::Error::from_str(
+            StatusCode::Forbidden,
+            format!("Token is missing the {:?} scope", scope),
+        )),
+        None => Err(tide::Error::from_str(
+            StatusCode::Unauthorized,
+            "Request was not authenticated",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_for() {
+        let auth = Auth::new(
+            serde_json::from_str(
+                r#"[
+                    {"token": "admin"},
+                    {"token": "reader", "scopes": ["read"]}
+                ]"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(auth.scopes_for("admin").unwrap(), ALL_SCOPES.to_vec());
+        assert_eq!(auth.scopes_for("reader").unwrap(), vec![Scope::Read]);
+        assert!(auth.scopes_for("read").is_none());
+        assert!(auth.scopes_for("").is_none());
+    }
+}
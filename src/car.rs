@@ -0,0 +1,140 @@
+//! Minimal CARv1 reader and writer, used to export and import the advertisement chain.
+//! Format: <https://ipld.io/specs/transport/car/carv1/>
+use crate::protobuf;
+use crate::verify::digest_matches;
+use forest_cid::Cid;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read};
+use thiserror::Error;
+
+pub const CAR_CONTENT_TYPE: &str = "application/vnd.ipld.car";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CarHeader {
+    roots: Vec<Cid>,
+    version: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum CarError {
+    #[error("Unexpected end of CAR data")]
+    UnexpectedEof,
+    #[error("Invalid varint")]
+    InvalidVarint,
+    #[error("Invalid CAR header: {0}")]
+    InvalidHeader(String),
+    #[error("Unsupported CAR version {0}")]
+    UnsupportedVersion(u64),
+    #[error("Invalid block cid: {0}")]
+    InvalidCid(forest_cid::Error),
+    #[error("Block does not match its cid: {0}")]
+    HashMismatch(Cid),
+}
+
+/// Unlike `protobuf::read_varint`, tells a clean end of the data, between sections, from a
+/// truncated varint.
+fn read_varint(r: &mut Cursor<&[u8]>) -> Result<Option<u64>, CarError> {
+    let mut n: u64 = 0;
+    for i in 0..10 {
+        let mut b = [0u8; 1];
+        if r.read(&mut b).map_err(|_| CarError::UnexpectedEof)? == 0 {
+            return if i == 0 {
+                Ok(None)
+            } else {
+                Err(CarError::UnexpectedEof)
+            };
+        }
+        n |= ((b[0] & 0x7f) as u64) << (7 * i);
+        if b[0] & 0x80 == 0 {
+            return Ok(Some(n));
+        }
+    }
+    Err(CarError::InvalidVarint)
+}
+
+/// Writes a CARv1 with a single root.
+pub fn write_car(root: Cid, blocks: impl IntoIterator<Item = (Cid, Vec<u8>)>) -> Vec<u8> {
+    let mut out = vec![];
+    let header = forest_encoding::to_vec(&CarHeader {
+        roots: vec![root],
+        version: 1,
+    })
+    .expect("CAR header is always serializable");
+    protobuf::put_varint(&mut out, header.len() as u64);
+    out.extend_from_slice(&header);
+
+    for (cid, bytes) in blocks {
+        let cid_bytes = cid.to_bytes();
+        protobuf::put_varint(&mut out, (cid_bytes.len() + bytes.len()) as u64);
+        out.extend_from_slice(&cid_bytes);
+        out.extend_from_slice(&bytes);
+    }
+    out
+}
+
+/// A block and the cid it is stored under.
+pub type Block = (Cid, Vec<u8>);
+
+/// Reads a CARv1, checking every block against its cid. Returns the roots and blocks.
+pub fn read_car(data: &[u8]) -> Result<(Vec<Cid>, Vec<Block>), CarError> {
+    let mut r = Cursor::new(data);
+    let header_len = read_varint(&mut r)?.ok_or(CarError::UnexpectedEof)? as usize;
+    let header_bytes = take(&mut r, header_len)?;
+    let header: CarHeader = forest_encoding::from_slice(header_bytes)
+        .map_err(|e| CarError::InvalidHeader(format!("{}", e)))?;
+    if header.version != 1 {
+        return Err(CarError::UnsupportedVersion(header.version));
+    }
+
+    let mut blocks = vec![];
+    while let Some(section_len) = read_varint(&mut r)? {
+        let section = take(&mut r, section_len as usize)?;
+        let mut section_reader = Cursor::new(section);
+        let cid = Cid::read_bytes(&mut section_reader).map_err(CarError::InvalidCid)?;
+        let bytes = section[section_reader.position() as usize..].to_vec();
+        digest_matches(&cid, &bytes).map_err(|_| CarError::HashMismatch(cid))?;
+        blocks.push((cid, bytes));
+    }
+
+    Ok((header.roots, blocks))
+}
+
+fn take<'a>(r: &mut Cursor<&'a [u8]>, len: usize) -> Result<&'a [u8], CarError> {
+    let start = r.position() as usize;
+    let data: &'a [u8] = r.get_ref();
+    let end = start.checked_add(len).ok_or(CarError::UnexpectedEof)?;
+    if end > data.len() {
+        return Err(CarError::UnexpectedEof);
+    }
+    r.set_position(end as u64);
+    Ok(&data[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_car_roundtrip() {
+        let a = b"block a".to_vec();
+        let b = b"block b".to_vec();
+        let a_cid = forest_cid::new_from_cbor(&a, forest_cid::Code::Blake2b256);
+        let b_cid = forest_cid::new_from_cbor(&b, forest_cid::Code::Blake2b256);
+
+        let car = write_car(a_cid, vec![(a_cid, a.clone()), (b_cid, b.clone())]);
+        let (roots, blocks) = read_car(&car).expect("failed to read car");
+        assert_eq!(roots, vec![a_cid]);
+        assert_eq!(blocks, vec![(a_cid, a), (b_cid, b)]);
+    }
+
+    #[test]
+    fn test_car_rejects_corrupt_block() {
+        let a = b"block a".to_vec();
+        let a_cid = forest_cid::new_from_cbor(&a, forest_cid::Code::Blake2b256);
+
+        let mut car = write_car(a_cid, vec![(a_cid, a)]);
+        let last = car.len() - 1;
+        car[last] ^= 0xff;
+        assert!(matches!(read_car(&car), Err(CarError::HashMismatch(_))));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::advertisement::{AdSigError, Advertisement, EntryChunk};
+use forest_cid::Cid;
+use forest_ipld::Ipld;
+use ipld_blockstore::BlockStore;
+use std::collections::HashSet;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ChainError {
+    #[error("Block not found: {0}")]
+    MissingBlock(Cid),
+    #[error("Invalid link in {0}")]
+    InvalidLink(Cid),
+    #[error("Failed to read block {0}: {1}")]
+    Store(Cid, String),
+    #[error("Bad signature on advertisement {0}: {1}")]
+    BadSignature(Cid, AdSigError),
+}
+
+/// Returns the cid behind an optional link field, such as `PreviousID` or `Next`.
+pub(crate) fn link(field: &Option<Ipld>, from: Cid) -> Result<Option<Cid>, ChainError> {
+    match field {
+        Some(Ipld::Link(cid)) => Ok(Some(*cid)),
+        None | Some(Ipld::Null) => Ok(None),
+        _ => Err(ChainError::InvalidLink(from)),
+    }
+}
+
+fn load<BS: BlockStore, T: serde::de::DeserializeOwned>(
+    bs: &BS,
+    cid: &Cid,
+) -> Result<T, ChainError> {
+    bs.get(cid)
+        .map_err(|e| ChainError::Store(*cid, format!("{}", e)))?
+        .ok_or(ChainError::MissingBlock(*cid))
+}
+
+/// Identifies what a removal ad applies to. Removals are scoped by provider as well as context,
+/// since several providers can use the same context id.
+pub(crate) fn removal_key(cid: Cid, ad: &Advertisement) -> Result<(String, Vec<u8>), ChainError> {
+    let context = forest_encoding::to_vec(&ad.ContextID)
+        .map_err(|e| ChainError::Store(cid, format!("{}", e)))?;
+    Ok((ad.Provider.clone(), context))
+}
+
+/// Iterates over the advertisement chain, starting at the head and following `PreviousID`.
+pub(crate) struct Ads<'a, BS> {
+    bs: &'a BS,
+    next: Option<Cid>,
+}
+
+pub(crate) fn ads<BS: BlockStore>(bs: &BS, head: Option<Cid>) -> Ads<'_, BS> {
+    Ads { bs, next: head }
+}
+
+impl<'a, BS: BlockStore> Iterator for Ads<'a, BS> {
+    type Item = Result<(Cid, Advertisement), ChainError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cid = self.next.take()?;
+        let res = load::<_, Advertisement>(self.bs, &cid).and_then(|ad| {
+            self.next = link(&ad.PreviousID, cid)?;
+            Ok((cid, ad))
+        });
+        Some(res)
+    }
+}
+
+/// Loads every entry chunk linked from the advertisement, following `Next`.
+pub(crate) fn entry_chunks<BS: BlockStore>(
+    bs: &BS,
+    ad_cid: Cid,
+    ad: &Advertisement,
+) -> Result<Vec<(Cid, EntryChunk)>, ChainError> {
+    chunk_list(bs, link(&ad.Entries, ad_cid)?)
+}
+
+/// Like `entry_chunks`, but returns nothing for an ad whose entries were pruned. Pruning removes
+/// whole lists, so only the first chunk needs checking.
+pub(crate) fn retained_entry_chunks<BS: BlockStore>(
+    bs: &BS,
+    ad_cid: Cid,
+    ad: &Advertisement,
+    pruned: &HashSet<Cid>,
+) -> Result<Vec<(Cid, EntryChunk)>, ChainError> {
+    match link(&ad.Entries, ad_cid)? {
+        Some(first) if pruned.contains(&first) => Ok(vec![]),
+        first => chunk_list(bs, first),
+    }
+}
+
+/// Loads the list of entry chunks starting at `first`, following `Next`.
+pub(crate) fn chunk_list<BS: BlockStore>(
+    bs: &BS,
+    first: Option<Cid>,
+) -> Result<Vec<(Cid, EntryChunk)>, ChainError> {
+    let mut chunks = vec![];
+    let mut next = first;
+    while let Some(cid) = next {
+        let chunk: EntryChunk = load(bs, &cid)?;
+        next = link(&chunk.Next, cid)?;
+        chunks.push((cid, chunk));
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::advertisement::AdvertisementBuilder;
+    use crate::tests::test_ad;
+    use forest_db::MemoryDB;
+    use multihash::MultihashDigest;
+
+    pub(crate) fn publish(bs: &MemoryDB, previous: Option<Cid>, context: &str) -> Cid {
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let mut builder = AdvertisementBuilder {
+            entries_link: None,
+            entry_count: 0,
+            ad: Advertisement {
+                Provider: libp2p::PeerId::from_public_key(&keypair.public()).to_base58(),
+                ..test_ad(context)
+            },
+        };
+        for i in 0..2 {
+            let mh = multihash::Code::Blake2b256.digest(format!("{}-{}", context, i).as_bytes());
+            builder
+                .link_entries(bs, vec![Ipld::Bytes(mh.to_bytes())])
+                .unwrap();
+        }
+        builder.ad.PreviousID = previous.map(Ipld::Link);
+        let ad = async_std::task::block_on(builder.build(&keypair)).unwrap();
+        bs.put(&ad, forest_cid::Code::Blake2b256).unwrap()
+    }
+
+    #[test]
+    fn test_walk_chain() {
+        let bs = MemoryDB::default();
+        let first = publish(&bs, None, "first");
+        let second = publish(&bs, Some(first), "second");
+
+        let walked: Vec<Cid> = ads(&bs, Some(second)).map(|res| res.unwrap().0).collect();
+        assert_eq!(walked, vec![second, first]);
+
+        let (cid, ad) = ads(&bs, Some(second)).next().unwrap().unwrap();
+        let chunks = entry_chunks(&bs, cid, &ad).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[1].1.Next.is_none());
+    }
+}
@@ -0,0 +1,680 @@
+//! Operator subcommands. Most talk to a running provider, over its admin API or, for reading the
+//! chain, its public port the way an indexer would. The key commands only touch key files.
+//!
+//! There is no offline mode working on the datastore directly: the blockstore is held in memory
+//! by the provider process, so there is nothing on disk to open.
+use crate::advertisement::Advertisement;
+use crate::car::{self, CAR_CONTENT_TYPE};
+use crate::client::{ClientError, ProviderClient};
+use crate::compact::CompactReport;
+use crate::keys::{KeyError, KeyRing, KeyType};
+use crate::signed_head::SignedHeadError;
+use crate::sync::{SyncClient, SyncError};
+use crate::verify::FsckReport;
+use forest_cid::Cid;
+use forest_ipld::Ipld;
+use libp2p::futures::StreamExt;
+use libp2p::PeerId;
+use multihash::Multihash;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+use tide::http::{Body, Method};
+
+const DEFAULT_ADMIN_ADDR: &str = "127.0.0.1:8071";
+const DEFAULT_PUBLIC_ADDR: &str = "127.0.0.1:8070";
+
+/// One line per subcommand, for the usage message.
+pub const USAGE: &[&str] = &[
+    "keygen [--type ed25519|secp256k1] <key.json>",
+    "show-id <key.json>",
+    "head",
+    "ads list",
+    "ads show <cid>",
+    "publish --context-id <id> --car <file.car> [--provider <peer id>] [--addr <multiaddr>]...",
+    "remove --context-id <id> [--provider <peer id>]",
+    "verify-chain",
+    "export <out.car> [until-cid]",
+    "import [--replace] <in.car>",
+    "fsck",
+    "compact [--gc]",
+];
+
+/// Multihashes per entry chunk when publishing a CAR file, as in the Go provider.
+const ENTRIES_PER_CHUNK: usize = 16384;
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("Invalid {0}: {1}")]
+    Invalid(&'static str, String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Key(#[from] KeyError),
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error(transparent)]
+    Sync(Box<SyncError>),
+    #[error("Invalid head: {0}")]
+    Head(#[from] SignedHeadError),
+    #[error("{0}")]
+    Corrupt(String),
+}
+
+impl CliError {
+    /// 2 for a command line that couldn't be understood, 3 for a chain that is corrupt or doesn't
+    /// verify, and 1 for anything else, such as a provider that can't be reached.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Corrupt(_) | CliError::Head(_) => 3,
+            CliError::Sync(e) if matches!(**e, SyncError::Client(_)) => 1,
+            CliError::Sync(_) => 3,
+            _ => 1,
+        }
+    }
+}
+
+impl From<SyncError> for CliError {
+    fn from(e: SyncError) -> Self {
+        CliError::Sync(Box::new(e))
+    }
+}
+
+/// A response body that couldn't be read or decoded.
+fn invalid_response(e: tide::Error) -> CliError {
+    ClientError::InvalidResponse(e.to_string()).into()
+}
+
+/// Runs the subcommand in `args`, printing to stdout. `None` when there is no subcommand, so the
+/// provider should be started instead.
+pub fn run(args: &[&str]) -> Option<Result<(), CliError>> {
+    let mut out = std::io::stdout();
+    Some(match args {
+        [] => return None,
+        ["keygen", path] => keygen(path, "ed25519", &mut out),
+        ["keygen", "--type", key_type, path] => keygen(path, key_type, &mut out),
+        ["show-id", path] => show_id(path, &mut out),
+        args => Cli::from_env().and_then(|mut cli| async_std::task::block_on(cli.command(args))),
+    })
+}
+
+/// `ADMIN_ADDR` or `PUBLIC_ADDR` as a url. A bare `host:port` means plain HTTP.
+fn env_url(var: &str, default: &str) -> String {
+    let addr = std::env::var(var).unwrap_or_else(|_| default.into());
+    if addr.contains("://") {
+        addr
+    } else {
+        format!("http://{}", addr)
+    }
+}
+
+/// Parses `--name value` pairs. Flags in `repeated` may be given more than once, the others at
+/// most once.
+fn flags<'a>(
+    args: &[&'a str],
+    single: &[&str],
+    repeated: &[&str],
+) -> Result<HashMap<&'a str, Vec<&'a str>>, CliError> {
+    let mut flags: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut args = args.iter().copied();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .filter(|name| single.contains(name) || repeated.contains(name))
+            .ok_or_else(|| CliError::Usage(format!("Unexpected argument {}", arg)))?;
+        let value = args
+            .next()
+            .ok_or_else(|| CliError::Usage(format!("Missing value for {}", arg)))?;
+        let values = flags.entry(name).or_default();
+        if !values.is_empty() && single.contains(&name) {
+            return Err(CliError::Usage(format!("{} given more than once", arg)));
+        }
+        values.push(value);
+    }
+    Ok(flags)
+}
+
+fn required<'a>(flags: &HashMap<&str, Vec<&'a str>>, name: &str) -> Result<&'a str, CliError> {
+    flags
+        .get(name)
+        .map(|values| values[0])
+        .ok_or_else(|| CliError::Usage(format!("--{} is required", name)))
+}
+
+/// Bytes such as context ids are shown as text when they are, and as base64 otherwise.
+fn display_bytes(ipld: &Ipld) -> String {
+    match ipld {
+        Ipld::Bytes(bytes) => match std::str::from_utf8(bytes) {
+            Ok(s) => s.into(),
+            Err(_) => base64::encode(bytes),
+        },
+        other => format!("{:?}", other),
+    }
+}
+
+fn display_link(ipld: &Option<Ipld>) -> Option<String> {
+    match ipld {
+        Some(Ipld::Link(cid)) => Some(cid.to_string()),
+        _ => None,
+    }
+}
+
+/// Creates a key file with a new key. Doesn't need a provider.
+pub fn keygen(path: &str, key_type: &str, out: &mut impl Write) -> Result<(), CliError> {
+    if std::path::Path::new(path).exists() {
+        return Err(CliError::Usage(format!("{} already exists", path)));
+    }
+    let key_type: KeyType = key_type.parse()?;
+    let keys = KeyRing::load_or_generate(path, key_type)?;
+    writeln!(out, "{}", keys.peer_id())?;
+    Ok(())
+}
+
+/// Prints the peer id of a key file, followed by the ids it has retired. Doesn't need a provider.
+pub fn show_id(path: &str, out: &mut impl Write) -> Result<(), CliError> {
+    let keys = KeyRing::load(path)?;
+    let ids = keys.peer_ids();
+    let (current, retired) = ids
+        .split_last()
+        .expect("a key ring always has a current key");
+    writeln!(out, "{}", current)?;
+    for id in retired {
+        writeln!(out, "{} (retired)", id)?;
+    }
+    Ok(())
+}
+
+/// The commands that talk to a running provider, writing what they show to `out`.
+pub struct Cli<W> {
+    client: ProviderClient,
+    sync: SyncClient,
+    out: W,
+}
+
+impl Cli<std::io::Stdout> {
+    /// Finds the provider at `ADMIN_ADDR` and `PUBLIC_ADDR`, trusting `PROVIDER_CA` and sending
+    /// `ADMIN_TOKEN`, and prints to stdout.
+    pub fn from_env() -> Result<Self, CliError> {
+        let public_url = env_url("PUBLIC_ADDR", DEFAULT_PUBLIC_ADDR);
+        let mut client =
+            ProviderClient::new(&env_url("ADMIN_ADDR", DEFAULT_ADMIN_ADDR), &public_url)?;
+        let mut sync = SyncClient::new(&public_url)?;
+        if let Ok(path) = std::env::var("PROVIDER_CA") {
+            client = client.with_ca(Path::new(&path))?;
+            sync = sync.with_ca(Path::new(&path))?;
+        }
+        if let Ok(token) = std::env::var("ADMIN_TOKEN") {
+            client = client.with_token(token);
+        }
+        Ok(Cli::new(client, sync, std::io::stdout()))
+    }
+}
+
+impl<W: Write> Cli<W> {
+    pub fn new(client: ProviderClient, sync: SyncClient, out: W) -> Self {
+        Cli { client, sync, out }
+    }
+
+    /// Runs one of the subcommands in `USAGE` other than the key commands.
+    pub async fn command(&mut self, args: &[&str]) -> Result<(), CliError> {
+        match args {
+            ["head"] => self.head().await,
+            ["ads", "list"] => self.list_ads().await,
+            ["ads", "show", cid] => self.show_ad(cid).await,
+            ["publish", flags @ ..] => self.publish(flags).await,
+            ["remove", flags @ ..] => self.remove(flags).await,
+            ["verify-chain"] => self.verify_chain().await,
+            ["export", out] => self.export(out, None).await,
+            ["export", out, until] => self.export(out, Some(until)).await,
+            ["import", path] => self.import(path, false).await,
+            ["import", "--replace", path] => self.import(path, true).await,
+            ["fsck"] => self.fsck().await,
+            ["compact"] => self.compact(false).await,
+            ["compact", "--gc"] => self.compact(true).await,
+            args => Err(CliError::Usage(format!(
+                "Unknown command {}",
+                args.join(" ")
+            ))),
+        }
+    }
+
+    /// The provider to advertise, from `--provider` or else the publisher's own id.
+    async fn provider_id(&self, flags: &HashMap<&str, Vec<&str>>) -> Result<String, CliError> {
+        if let Some(provider) = flags.get("provider") {
+            return Ok(provider[0].into());
+        }
+        let mut res = self.client.admin(Method::Get, "keys", None).await?;
+        let keys: serde_json::Value = res.body_json().await.map_err(invalid_response)?;
+        keys["current"].as_str().map(String::from).ok_or_else(|| {
+            ClientError::InvalidResponse("The provider did not report its key".into()).into()
+        })
+    }
+
+    /// Prints the head, after checking its signature.
+    async fn head(&mut self) -> Result<(), CliError> {
+        match self.client.head().await? {
+            Some(signed_head) => {
+                let (pk, head) = signed_head.open()?;
+                writeln!(
+                    self.out,
+                    "{} (signed by {})",
+                    head,
+                    PeerId::from_public_key(&pk)
+                )?;
+            }
+            None => writeln!(self.out, "Nothing has been published yet")?,
+        }
+        Ok(())
+    }
+
+    /// Lists the ads from the head back to the start of the chain, one per line.
+    async fn list_ads(&mut self) -> Result<(), CliError> {
+        let head = match self.sync.head().await? {
+            Some((_, head)) => head,
+            None => return Ok(()),
+        };
+        for (cid, ad) in self.sync.ads_since(head, None).await? {
+            writeln!(
+                self.out,
+                "{} {} {}{}",
+                cid,
+                ad.Provider,
+                display_bytes(&ad.ContextID),
+                if ad.IsRm { " (removal)" } else { "" }
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Prints one ad as JSON, after checking its signature.
+    async fn show_ad(&mut self, cid: &str) -> Result<(), CliError> {
+        let cid: Cid = cid
+            .parse()
+            .map_err(|e: forest_cid::Error| CliError::Invalid("cid", e.to_string()))?;
+        let ad: Advertisement = self.sync.ad(cid).await?;
+        let metadata = match &ad.Metadata {
+            Ipld::Bytes(bytes) => base64::encode(bytes),
+            other => format!("{:?}", other),
+        };
+        let shown = serde_json::json!({
+            "cid": cid.to_string(),
+            "PreviousID": display_link(&ad.PreviousID),
+            "Provider": ad.Provider,
+            "Addresses": ad.Addresses,
+            "Entries": display_link(&ad.Entries),
+            "ContextID": display_bytes(&ad.ContextID),
+            "Metadata": metadata,
+            "IsRm": ad.IsRm,
+        });
+        writeln!(self.out, "{:#}", shown)?;
+        Ok(())
+    }
+
+    /// Advertises every block in a CAR file under a context id.
+    async fn publish(&mut self, args: &[&str]) -> Result<(), CliError> {
+        let flags = flags(args, &["context-id", "car", "provider"], &["addr"])?;
+        let context_id = required(&flags, "context-id")?;
+        let (_, blocks) = car::read_car(&async_std::fs::read(required(&flags, "car")?).await?)
+            .map_err(|e| CliError::Invalid("CAR file", e.to_string()))?;
+        // The CAR reader's cids carry their own multihash type, so go through the bytes.
+        let multihashes = blocks
+            .iter()
+            .map(|(cid, _)| Multihash::from_bytes(&cid.hash().to_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CliError::Invalid("CAR file", e.to_string()))?;
+
+        let ad = Advertisement {
+            PreviousID: None,
+            Provider: self.provider_id(&flags).await?,
+            Addresses: flags
+                .get("addr")
+                .map(|addrs| addrs.iter().map(|addr| addr.to_string()).collect())
+                .unwrap_or_default(),
+            Signature: Ipld::Bytes(vec![]),
+            Entries: None,
+            ContextID: Ipld::Bytes(context_id.into()),
+            Metadata: Ipld::Bytes(vec![]),
+            IsRm: false,
+        };
+        let id = self.client.create(&ad).await?;
+        for chunk in multihashes.chunks(ENTRIES_PER_CHUNK) {
+            self.client.add_entries(id, chunk).await?;
+        }
+        let cid = self.client.publish(id).await?;
+        writeln!(
+            self.out,
+            "Published {} multihashes under {} as {}",
+            multihashes.len(),
+            context_id,
+            cid
+        )?;
+        Ok(())
+    }
+
+    /// Tells indexers the provider no longer has a context's entries.
+    async fn remove(&mut self, args: &[&str]) -> Result<(), CliError> {
+        let flags = flags(args, &["context-id", "provider"], &[])?;
+        let context_id = required(&flags, "context-id")?;
+        let provider = self.provider_id(&flags).await?;
+        let cid = self.client.remove(&provider, context_id.as_bytes()).await?;
+        writeln!(self.out, "Removed {} as {}", context_id, cid)?;
+        Ok(())
+    }
+
+    /// Syncs the whole chain from the public port, checking every block and signature as an indexer
+    /// would. Fails on the first bad one.
+    async fn verify_chain(&mut self) -> Result<(), CliError> {
+        let (head, events) = self.sync.sync(None).await?;
+        let mut events = Box::pin(events);
+        let (mut ads, mut multihashes) = (0, 0);
+        while let Some(event) = events.next().await {
+            ads += 1;
+            multihashes += event?.multihashes.len();
+        }
+        writeln!(
+            self.out,
+            "Verified {} ads with {} multihashes, head is {}",
+            ads,
+            multihashes,
+            head.map(|cid| cid.to_string())
+                .unwrap_or_else(|| "empty".into())
+        )?;
+        Ok(())
+    }
+
+    /// Writes the chain from the head back to `until` (or the start of the chain) to a CAR file.
+    async fn export(&mut self, out: &str, until: Option<&str>) -> Result<(), CliError> {
+        let path = match until {
+            Some(until) => format!("export?until={}", until),
+            None => "export".into(),
+        };
+        let mut res = self.client.admin(Method::Get, &path, None).await?;
+        let car = res.body_bytes().await.map_err(invalid_response)?;
+        async_std::fs::write(out, car).await?;
+        writeln!(self.out, "Exported chain to {}", out)?;
+        Ok(())
+    }
+
+    /// Loads a CAR file exported by another provider and makes its root the head. With `replace` an
+    /// unrelated chain replaces the current one.
+    async fn import(&mut self, path: &str, replace: bool) -> Result<(), CliError> {
+        let mut body = Body::from_bytes(async_std::fs::read(path).await?);
+        body.set_mime(CAR_CONTENT_TYPE);
+        let path = if replace {
+            "import?replace=true"
+        } else {
+            "import"
+        };
+        let mut res = self.client.admin(Method::Post, path, Some(body)).await?;
+        let head = res.body_string().await.map_err(invalid_response)?;
+        writeln!(self.out, "Imported chain, head is now {}", head)?;
+        Ok(())
+    }
+
+    /// Asks the server to verify every block reachable from the head, and every ad's signature.
+    async fn fsck(&mut self) -> Result<(), CliError> {
+        let mut res = self.client.admin(Method::Get, "fsck", None).await?;
+        let report: FsckReport = res.body_json().await.map_err(invalid_response)?;
+        for error in &report.errors {
+            writeln!(self.out, "{}", error)?;
+        }
+        writeln!(
+            self.out,
+            "Checked {} blocks, {} problems found",
+            report.checked,
+            report.errors.len()
+        )?;
+        if let Some(cid) = &report.first_bad_signature {
+            writeln!(
+                self.out,
+                "First advertisement with a bad signature: {}",
+                cid
+            )?;
+        }
+        if !report.errors.is_empty() {
+            return Err(CliError::Corrupt("Chain is corrupt".into()));
+        }
+        Ok(())
+    }
+
+    /// Replaces the chain with one holding only the live ads, and optionally collects the old one.
+    async fn compact(&mut self, gc: bool) -> Result<(), CliError> {
+        let path = if gc { "compact?gc=true" } else { "compact" };
+        let mut res = self.client.admin(Method::Post, path, None).await?;
+        let report: CompactReport = res.body_json().await.map_err(invalid_response)?;
+        writeln!(
+            self.out,
+            "Compacted {} ads into {}, head is now {}",
+            report.ads_before,
+            report.ads_after,
+            report.head.as_deref().unwrap_or("empty")
+        )?;
+        if let Some(gc) = report.gc {
+            writeln!(
+                self.out,
+                "Collected {} blocks, {} bytes freed",
+                gc.swept, gc.bytes_freed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::tests::serve;
+    use ipld_blockstore::BlockStore;
+
+    fn temp_path(ext: &str) -> String {
+        let path = std::env::temp_dir().join(format!("cli-{:016x}.{}", rand::random::<u64>(), ext));
+        path.to_str().unwrap().into()
+    }
+
+    /// A command line tool for a provider serving both APIs at `url`.
+    fn cli(url: &str) -> Cli<Vec<u8>> {
+        Cli::new(
+            ProviderClient::new(url, url).unwrap(),
+            SyncClient::new(url).unwrap(),
+            vec![],
+        )
+    }
+
+    /// Runs a command, returning what it printed.
+    async fn output(cli: &mut Cli<Vec<u8>>, args: &[&str]) -> Result<String, CliError> {
+        cli.command(args).await?;
+        Ok(String::from_utf8(std::mem::take(&mut cli.out)).unwrap())
+    }
+
+    #[test]
+    fn test_flags() {
+        let args = [
+            "--context-id",
+            "deal-1",
+            "--addr",
+            "/ip4/1.2.3.4/tcp/1",
+            "--addr",
+            "/dns/x",
+        ];
+        let parsed = flags(&args, &["context-id", "car"], &["addr"]).unwrap();
+        assert_eq!(required(&parsed, "context-id").unwrap(), "deal-1");
+        assert_eq!(parsed["addr"], vec!["/ip4/1.2.3.4/tcp/1", "/dns/x"]);
+        assert!(required(&parsed, "car").is_err());
+
+        assert!(flags(&["--context-id"], &["context-id"], &[]).is_err());
+        assert!(flags(&["--other", "x"], &["context-id"], &[]).is_err());
+        assert!(flags(&["deal-1"], &["context-id"], &[]).is_err());
+        let twice = ["--context-id", "a", "--context-id", "b"];
+        assert!(flags(&twice, &["context-id"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_keygen() {
+        let path = std::env::temp_dir().join(format!("cli-{:016x}.json", rand::random::<u64>()));
+        let path = path.to_str().unwrap();
+        let mut out = vec![];
+        keygen(path, "secp256k1", &mut out).unwrap();
+        let keys = KeyRing::load(path).unwrap();
+        // Peer ids of secp256k1 keys embed the key, which gives them this prefix.
+        assert!(keys.peer_id().to_base58().starts_with("16Uiu2"));
+        let mut shown = vec![];
+        show_id(path, &mut shown).unwrap();
+        assert_eq!(shown, out);
+        // Never overwrite an existing key.
+        let err = keygen(path, "ed25519", &mut out).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+        assert_eq!(KeyRing::load(path).unwrap().peer_id(), keys.peer_id());
+        assert!(keygen(&format!("{}.new", path), "rsa", &mut out).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_commands() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider();
+            let publisher = provider.keys.read().await.peer_id().to_base58();
+            let url = serve(crate::tests::test_app(provider.clone())).await;
+            let mut cli = cli(&url);
+
+            let out = output(&mut cli, &["head"]).await.unwrap();
+            assert_eq!(out, "Nothing has been published yet\n");
+            assert_eq!(output(&mut cli, &["ads", "list"]).await.unwrap(), "");
+
+            let blocks: Vec<_> = [b"block a".to_vec(), b"block b".to_vec()]
+                .into_iter()
+                .map(|block| {
+                    let cid = forest_cid::new_from_cbor(&block, forest_cid::Code::Blake2b256);
+                    (cid, block)
+                })
+                .collect();
+            let car_path = temp_path("car");
+            std::fs::write(&car_path, car::write_car(blocks[0].0, blocks.clone())).unwrap();
+            let publish = [
+                "publish",
+                "--context-id",
+                "deal-1",
+                "--car",
+                &car_path,
+                "--addr",
+                "/ip4/1.2.3.4/tcp/1",
+            ];
+            let out = output(&mut cli, &publish).await.unwrap();
+            let published = provider.head.read().await.unwrap();
+            assert_eq!(
+                out,
+                format!("Published 2 multihashes under deal-1 as {}\n", published)
+            );
+            std::fs::remove_file(&car_path).unwrap();
+
+            let out = output(&mut cli, &["head"]).await.unwrap();
+            assert_eq!(out, format!("{} (signed by {})\n", published, publisher));
+
+            let cid = published.to_string();
+            let out = output(&mut cli, &["ads", "show", &cid]).await.unwrap();
+            let shown: serde_json::Value = serde_json::from_str(&out).unwrap();
+            assert_eq!(shown["cid"], cid);
+            assert_eq!(shown["Provider"], publisher);
+            assert_eq!(shown["ContextID"], "deal-1");
+            assert_eq!(
+                shown["Addresses"],
+                serde_json::json!(["/ip4/1.2.3.4/tcp/1"])
+            );
+            assert_eq!(shown["IsRm"], false);
+
+            let out = output(&mut cli, &["remove", "--context-id", "deal-1"])
+                .await
+                .unwrap();
+            let removal = provider.head.read().await.unwrap();
+            assert_eq!(out, format!("Removed deal-1 as {}\n", removal));
+
+            let out = output(&mut cli, &["ads", "list"]).await.unwrap();
+            assert_eq!(
+                out,
+                format!(
+                    "{} {} deal-1 (removal)\n{} {} deal-1\n",
+                    removal, publisher, published, publisher
+                )
+            );
+
+            let out = output(&mut cli, &["verify-chain"]).await.unwrap();
+            assert_eq!(
+                out,
+                format!("Verified 2 ads with 2 multihashes, head is {}\n", removal)
+            );
+
+            let export_path = temp_path("car");
+            let out = output(&mut cli, &["export", &export_path]).await.unwrap();
+            assert_eq!(out, format!("Exported chain to {}\n", export_path));
+            let (roots, exported) = car::read_car(&std::fs::read(&export_path).unwrap()).unwrap();
+            assert_eq!(roots, vec![removal]);
+            assert!(exported.iter().any(|(cid, _)| *cid == published));
+            std::fs::remove_file(&export_path).unwrap();
+
+            let out = output(&mut cli, &["fsck"]).await.unwrap();
+            assert!(out.ends_with(", 0 problems found\n"));
+        })
+    }
+
+    #[test]
+    fn test_command_errors() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider();
+            let url = serve(crate::tests::test_app(provider.clone())).await;
+            let mut cli = cli(&url);
+
+            for args in [
+                &["publish", "--context-id", "deal-1"][..],
+                &["remove"],
+                &["ads"],
+                &["nonsense"],
+            ] {
+                let err = cli.command(args).await.unwrap_err();
+                assert!(matches!(err, CliError::Usage(_)), "{:?}", args);
+                assert_eq!(err.exit_code(), 2);
+            }
+            let err = cli.command(&["ads", "show", "bafy"]).await.unwrap_err();
+            assert!(matches!(err, CliError::Invalid("cid", _)));
+            assert_eq!(err.exit_code(), 1);
+
+            // A head whose signature no longer covers its fields, stored under its own cid so
+            // only the signature check catches it.
+            output(&mut cli, &["remove", "--context-id", "deal-1"])
+                .await
+                .unwrap();
+            let forged = {
+                let head = provider.head.read().await.unwrap();
+                let bs = provider.blockstore.read().await;
+                let mut ad: Advertisement = bs.get(&head).unwrap().unwrap();
+                ad.Addresses = vec!["/ip4/10.0.0.1/tcp/9999".into()];
+                bs.put(&ad, forest_cid::Code::Blake2b256).unwrap()
+            };
+            *provider.head.write().await = Some(forged);
+            let err = cli.command(&["verify-chain"]).await.unwrap_err();
+            assert_eq!(err.exit_code(), 3);
+            let err = cli.command(&["fsck"]).await.unwrap_err();
+            assert!(matches!(err, CliError::Corrupt(_)));
+            assert_eq!(err.exit_code(), 3);
+            let out = String::from_utf8(std::mem::take(&mut cli.out)).unwrap();
+            assert!(out.ends_with(&format!(
+                "First advertisement with a bad signature: {}\n",
+                forged
+            )));
+
+            // Nothing listening.
+            let port = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let mut cli = self::cli(&format!("http://127.0.0.1:{}", port));
+            let err = cli.command(&["head"]).await.unwrap_err();
+            assert!(matches!(err, CliError::Client(_)));
+            assert_eq!(err.exit_code(), 1);
+        })
+    }
+}
@@ -0,0 +1,220 @@
+//! A typed client for the admin API, plus the public head route, so integrators don't have to
+//! hand-roll the create, entryChunk and publish requests.
+use crate::advertisement::Advertisement;
+use crate::signed_head::SignedHead;
+use crate::transport::{Endpoint, TransportError};
+use forest_cid::Cid;
+use forest_ipld::Ipld;
+use multihash::Multihash;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use thiserror::Error;
+use tide::http::headers::AUTHORIZATION;
+use tide::http::{Body, Method, Request, Response};
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error("Invalid response from the provider: {0}")]
+    InvalidResponse(String),
+    #[error("Failed to encode the request: {0}")]
+    Encoding(String),
+}
+
+/// Talks to one chain of a provider: the admin API for building and publishing ads, and the
+/// public port for the head.
+#[derive(Debug, Clone)]
+pub struct ProviderClient {
+    admin: Endpoint,
+    public: Endpoint,
+    token: Option<String>,
+}
+
+impl ProviderClient {
+    /// Takes the base urls of the admin and public apps, e.g. `http://127.0.0.1:8071` and
+    /// `http://127.0.0.1:8070`. A tenant's chain is reached by adding `/p/<peer id>` to both.
+    /// Either can also be an `https` or `http+unix` url.
+    pub fn new(admin_url: &str, public_url: &str) -> Result<Self, ClientError> {
+        Ok(ProviderClient {
+            admin: Endpoint::new(admin_url)?,
+            public: Endpoint::new(public_url)?,
+            token: None,
+        })
+    }
+
+    /// Trusts the CA certificates in the PEM file at `path` on both ports, for a provider with a
+    /// certificate from a private CA.
+    pub fn with_ca(self, path: &Path) -> Result<Self, ClientError> {
+        Ok(ProviderClient {
+            admin: self.admin.with_ca(path)?,
+            public: self.public.with_ca(path)?,
+            ..self
+        })
+    }
+
+    /// Sends `Authorization: Bearer <token>` with every admin request.
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        ProviderClient {
+            token: Some(token.into()),
+            ..self
+        }
+    }
+
+    /// Starts an ad from its fields, except for the entries. Returns its temporary id.
+    pub async fn create(&self, ad: &Advertisement) -> Result<i64, ClientError> {
+        let body = forest_encoding::to_vec(ad).map_err(|e| ClientError::Encoding(e.to_string()))?;
+        let body = Some(Body::from_bytes(body));
+        let mut res = self.admin(Method::Post, "create", body).await?;
+        json(&mut res).await
+    }
+
+    /// Links another chunk of entries to the ad being built.
+    pub async fn add_entries(&self, id: i64, entries: &[Multihash]) -> Result<(), ClientError> {
+        let entries: Vec<Ipld> = entries
+            .iter()
+            .map(|mh| Ipld::Bytes(mh.to_bytes()))
+            .collect();
+        let body =
+            forest_encoding::to_vec(&entries).map_err(|e| ClientError::Encoding(e.to_string()))?;
+        let path = format!("adv/{}/entryChunk", id);
+        self.admin(Method::Post, &path, Some(Body::from_bytes(body)))
+            .await?;
+        Ok(())
+    }
+
+    /// Signs the ad, makes it the head and returns its cid.
+    pub async fn publish(&self, id: i64) -> Result<Cid, ClientError> {
+        let path = format!("adv/{}/publish", id);
+        let mut res = self.admin(Method::Post, &path, None).await?;
+        let cid = res
+            .body_string()
+            .await
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        cid.parse()
+            .map_err(|e: forest_cid::Error| ClientError::InvalidResponse(e.to_string()))
+    }
+
+    /// Publishes an `IsRm` ad telling indexers the provider no longer has the context's entries.
+    pub async fn remove(&self, provider: &str, context_id: &[u8]) -> Result<Cid, ClientError> {
+        let ad = Advertisement {
+            PreviousID: None,
+            Provider: provider.into(),
+            Addresses: vec![],
+            Signature: Ipld::Bytes(vec![]),
+            Entries: None,
+            ContextID: Ipld::Bytes(context_id.to_vec()),
+            Metadata: Ipld::Bytes(vec![]),
+            IsRm: true,
+        };
+        let id = self.create(&ad).await?;
+        self.publish(id).await
+    }
+
+    /// The signed head from the public port, or `None` if nothing has been published yet. It still
+    /// needs to be opened to check the signature.
+    pub async fn head(&self) -> Result<Option<SignedHead>, ClientError> {
+        let url = self.public.url("head")?;
+        match self.public.send(Request::new(Method::Get, url)).await {
+            Ok(mut res) => json(&mut res).await.map(Some),
+            Err(TransportError::Status(404, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Sends a request to an admin route, relative to the chain's base url, for the routes this
+    /// client has no method for.
+    pub(crate) async fn admin(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Body>,
+    ) -> Result<Response, ClientError> {
+        let mut req = Request::new(method, self.admin.url(path)?);
+        if let Some(token) = &self.token {
+            req.insert_header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        if let Some(body) = body {
+            req.set_body(body);
+        }
+        Ok(self.admin.send(req).await?)
+    }
+}
+
+async fn json<T: DeserializeOwned>(res: &mut Response) -> Result<T, ClientError> {
+    res.body_json()
+        .await
+        .map_err(|e| ClientError::InvalidResponse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::tests::serve;
+    use crate::tests::test_ad;
+    use multihash::MultihashDigest;
+
+    #[test]
+    fn test_client() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider();
+            let url = serve(crate::tests::test_app(provider.clone())).await;
+            let client = ProviderClient::new(&url, &url).unwrap();
+            assert!(client.head().await.unwrap().is_none());
+
+            let id = client.create(&test_ad("client")).await.unwrap();
+            for i in 0..3 {
+                let mh = multihash::Code::Sha2_256.digest(format!("entry {}", i).as_bytes());
+                client.add_entries(id, &[mh]).await.unwrap();
+            }
+            let cid = client.publish(id).await.unwrap();
+            let (_, head) = client.head().await.unwrap().unwrap().open().unwrap();
+            assert_eq!(head, cid);
+            let bs = provider.blockstore.read().await;
+            let ad: Advertisement = ipld_blockstore::BlockStore::get(&*bs, &cid)
+                .unwrap()
+                .unwrap();
+            ad.verify_sig().unwrap();
+            assert_eq!(crate::chain::entry_chunks(&*bs, cid, &ad).unwrap().len(), 3);
+            drop(bs);
+
+            let removal = client.remove(&ad.Provider, b"client").await.unwrap();
+            let (pk, head) = client.head().await.unwrap().unwrap().open().unwrap();
+            assert_eq!(head, removal);
+
+            // Routes without a method of their own, as the operator commands use them.
+            let mut res = client.admin(Method::Get, "keys", None).await.unwrap();
+            let keys: serde_json::Value = res.body_json().await.unwrap();
+            let publisher = libp2p::PeerId::from_public_key(&pk).to_base58();
+            assert_eq!(keys["current"], publisher);
+
+            // Errors carry the status and message from the provider.
+            assert!(matches!(
+                client.publish(id).await,
+                Err(ClientError::Transport(TransportError::Status(404, _)))
+            ));
+        })
+    }
+
+    #[test]
+    fn test_client_token() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider();
+            let mut app = tide::with_state(provider);
+            app.with(crate::auth::Auth::new(vec![crate::auth::TokenConfig {
+                token: "secret".into(),
+                scopes: None,
+            }]));
+            crate::mount_admin(&mut app, &crate::limits::BodyLimits::default());
+            let url = serve(app).await;
+
+            let client = ProviderClient::new(&url, &url).unwrap();
+            assert!(matches!(
+                client.create(&test_ad("token")).await,
+                Err(ClientError::Transport(TransportError::Status(401, _)))
+            ));
+            let client = client.with_token("secret");
+            client.create(&test_ad("token")).await.unwrap();
+        })
+    }
+}
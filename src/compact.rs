@@ -0,0 +1,114 @@
+//! Chain compaction. A long-running provider's chain is mostly ads that were later undone by a
+//! removal, which new indexers still have to ingest. Compaction republishes only the ads that are
+//! still live, and the removals indexers may not have seen yet, as a fresh chain.
+use crate::advertisement::Advertisement;
+use crate::chain::{self, ChainError};
+use crate::gc::GcReport;
+use crate::keys::ROTATION_CONTEXT_ID;
+use forest_cid::Cid;
+use forest_ipld::Ipld;
+use ipld_blockstore::BlockStore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CompactReport {
+    /// Head of the new chain.
+    pub head: Option<String>,
+    pub ads_before: usize,
+    pub ads_after: usize,
+    /// Set when garbage collection was run after compacting.
+    pub gc: Option<GcReport>,
+}
+
+/// Returns the ads to keep, oldest first, along with the length of the chain. An ad is live
+/// unless a later ad from the same provider removed its context. The newest removal of each
+/// context is kept too, in its place: an indexer that ingested a removed ad before the removal
+/// can't find its last seen ad in the new chain, so it re-ingests it and must still see the
+/// removal. Key rotation announcements are left out.
+pub(crate) fn live_ads<BS: BlockStore>(
+    bs: &BS,
+    head: Option<Cid>,
+) -> Result<(Vec<(Cid, Advertisement)>, usize), ChainError> {
+    let mut removed = HashSet::new();
+    let mut kept = vec![];
+    let mut total = 0;
+    for res in chain::ads(bs, head) {
+        let (cid, ad) = res?;
+        total += 1;
+        let key = chain::removal_key(cid, &ad)?;
+        if ad.IsRm {
+            if removed.insert(key) {
+                kept.push((cid, ad));
+            }
+        } else if ad.ContextID == Ipld::Bytes(ROTATION_CONTEXT_ID.to_vec()) {
+            // A handover has no entries to keep, and indexers that followed the old chain have
+            // already seen it. New indexers learn the current publisher key from the signed head.
+            continue;
+        } else if !removed.contains(&key) {
+            kept.push((cid, ad));
+        }
+    }
+    kept.reverse();
+    Ok((kept, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{put_test_ad, test_ad};
+    use forest_db::MemoryDB;
+
+    fn publish(bs: &MemoryDB, previous: Option<Cid>, context: &str, is_rm: bool) -> Cid {
+        let ad = Advertisement {
+            IsRm: is_rm,
+            ..test_ad(context)
+        };
+        put_test_ad(bs, previous, ad, &[])
+    }
+
+    #[test]
+    fn test_live_ads() {
+        let bs = MemoryDB::default();
+        let a = publish(&bs, None, "a", false);
+        let b = publish(&bs, Some(a), "b", false);
+        let a_again = publish(&bs, Some(b), "a", false);
+        let rm_a = publish(&bs, Some(a_again), "a", true);
+        let c = publish(&bs, Some(rm_a), "c", false);
+        let a_readded = publish(&bs, Some(c), "a", false);
+
+        let rm_a_again = publish(&bs, Some(a_readded), "a", true);
+        let d = publish(&bs, Some(rm_a_again), "d", false);
+
+        // Only the newest removal of "a" is kept, and the ads it follows are dropped.
+        let (live, total) = live_ads(&bs, Some(d)).unwrap();
+        let live: Vec<Cid> = live.into_iter().map(|(cid, _)| cid).collect();
+        assert_eq!(live, vec![b, c, rm_a_again, d]);
+        assert_eq!(total, 8);
+
+        let (live, _) = live_ads(&bs, Some(a_readded)).unwrap();
+        let live: Vec<Cid> = live.into_iter().map(|(cid, _)| cid).collect();
+        assert_eq!(live, vec![b, rm_a, c, a_readded]);
+
+        let (live, total) = live_ads(&bs, None).unwrap();
+        assert!(live.is_empty());
+        assert_eq!(total, 0);
+    }
+
+    #[test]
+    fn test_live_ads_per_provider() {
+        let bs = MemoryDB::default();
+        let mine = publish(&bs, None, "deal", false);
+        let theirs = Advertisement {
+            Provider: "12D3KooWQYhTNQdmr3ArTeUHRYzFg94BKyTkoWBDWez9kSCVe2Xo".into(),
+            ..test_ad("deal")
+        };
+        let theirs = put_test_ad(&bs, Some(mine), theirs, &[]);
+        let rm = publish(&bs, Some(theirs), "deal", true);
+
+        // The removal only applies to the provider that published it.
+        let (live, _) = live_ads(&bs, Some(rm)).unwrap();
+        let live: Vec<Cid> = live.into_iter().map(|(cid, _)| cid).collect();
+        assert_eq!(live, vec![theirs, rm]);
+    }
+}
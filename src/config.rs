@@ -0,0 +1,171 @@
+use crate::auth::TokenConfig;
+use crate::keys::KeyType;
+use crate::limits::{BodyLimits, RateLimitConfig};
+use crate::prune::PruneConfig;
+use crate::signer::RemoteSignerConfig;
+use crate::tls::TlsConfig;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Env var pointing at the JSON config file. Without it the defaults below are used.
+pub const CONFIG_ENV: &str = "INDEX_PROVIDER_CONFIG";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address of the public app serving the head and blocks to indexers.
+    pub listen_addr: String,
+    /// Address of the admin app used to build and publish advertisements.
+    pub admin_listen_addr: String,
+    /// Path the public routes are mounted under, for running behind a reverse proxy.
+    /// E.g. `/provider` serves `/provider/head` and `/provider/ipni/v1/ad/head`.
+    pub path_prefix: String,
+    /// Bearer tokens accepted by the admin app. The admin app is unauthenticated when empty.
+    pub admin_tokens: Vec<TokenConfig>,
+    /// Serve the public app over TLS.
+    pub tls: Option<TlsConfig>,
+    /// Serve the admin app over TLS.
+    pub admin_tls: Option<TlsConfig>,
+    /// Serve the admin app on this Unix domain socket instead of `admin_listen_addr`.
+    pub admin_unix_socket: Option<String>,
+    /// Maximum request body size of each admin route.
+    pub body_limits: BodyLimits,
+    /// Per-client rate limit on the public app. Unlimited when omitted.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// How long to wait for in-flight admin requests, such as publishes, on shutdown.
+    pub drain_timeout_secs: u64,
+    /// Run garbage collection every this many seconds. Only on `POST /gc` when omitted.
+    pub gc_interval_secs: Option<u64>,
+    /// Delete the entries of removed contexts after a grace period. Entries are kept forever
+    /// when omitted.
+    pub prune: Option<PruneConfig>,
+    /// JSON key file holding the publisher key and its rotation history. Created on first start.
+    /// A new key is generated on every start when omitted.
+    pub key_path: Option<std::path::PathBuf>,
+    /// Type of the keys generated on first start and on rotation, `ed25519` or `secp256k1`.
+    pub key_type: KeyType,
+    /// Key files of the storage providers this publisher serves. Ads for these providers are
+    /// signed with their own key instead of the publisher key.
+    pub provider_key_paths: Vec<std::path::PathBuf>,
+    /// Remote signer holding the publisher key, in place of `key_path`.
+    pub signer: Option<RemoteSignerConfig>,
+    /// Remote signers holding storage provider keys, alongside `provider_key_paths`.
+    pub provider_signers: Vec<RemoteSignerConfig>,
+    /// Reject ads for providers without a key.
+    pub require_provider_key: bool,
+    /// Further chains hosted by this process, each under `/p/<peer id>` on both ports.
+    pub tenants: Vec<TenantConfig>,
+    /// Check the signature of each ad the first time it is served, as well as its cid. `fsck`
+    /// checks them all on demand either way.
+    pub verify_on_serve: bool,
+    /// Sign a sequence number and timestamp into the head, so readers can reject replayed or
+    /// stale heads.
+    pub extended_head: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    /// Key file of the tenant, which signs both its head and its ads. Created on first start.
+    pub key_path: std::path::PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen_addr: "0.0.0.0:8070".into(),
+            admin_listen_addr: "0.0.0.0:8071".into(),
+            path_prefix: "".into(),
+            admin_tokens: vec![],
+            tls: None,
+            admin_tls: None,
+            admin_unix_socket: None,
+            body_limits: BodyLimits::default(),
+            rate_limit: None,
+            drain_timeout_secs: 30,
+            gc_interval_secs: None,
+            prune: None,
+            key_path: None,
+            key_type: KeyType::default(),
+            provider_key_paths: vec![],
+            signer: None,
+            provider_signers: vec![],
+            require_provider_key: false,
+            tenants: vec![],
+            verify_on_serve: false,
+            extended_head: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Loads the config file named by `INDEX_PROVIDER_CONFIG`, if set.
+    pub fn load() -> Result<Self, std::io::Error> {
+        match std::env::var_os(CONFIG_ENV) {
+            Some(path) => Config::from_file(path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Where tide should listen for the admin app, when it isn't served over TLS.
+    pub fn admin_listener(&self) -> Result<String, std::io::Error> {
+        match (&self.admin_unix_socket, &self.admin_tls) {
+            (Some(_), Some(_)) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "admin_tls can't be combined with admin_unix_socket",
+            )),
+            (Some(path), None) => Ok(format!("http+unix://{}", path)),
+            (None, _) => Ok(self.admin_listen_addr.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_config() {
+        let config: Config =
+            serde_json::from_str(r#"{"path_prefix": "/provider"}"#).expect("deser failed");
+        assert_eq!(config.path_prefix, "/provider");
+        assert_eq!(config.listen_addr, Config::default().listen_addr);
+
+        let config: Config =
+            serde_json::from_str(r#"{"body_limits": {"create": 100}}"#).expect("deser failed");
+        assert_eq!(config.body_limits.create, 100);
+        assert_eq!(config.body_limits.import, BodyLimits::default().import);
+
+        assert!(serde_json::from_str::<Config>(r#"{"unknown": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_admin_listener() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "tls": {"cert_path": "cert.pem", "key_path": "key.pem"},
+                "admin_unix_socket": "/run/provider/admin.sock"
+            }"#,
+        )
+        .expect("deser failed");
+        assert_eq!(config.tls.as_ref().unwrap().reload_interval_secs, 30);
+        assert_eq!(
+            config.admin_listener().unwrap(),
+            "http+unix:///run/provider/admin.sock"
+        );
+
+        let config: Config = serde_json::from_str(
+            r#"{
+                "admin_tls": {"cert_path": "cert.pem", "key_path": "key.pem"},
+                "admin_unix_socket": "/run/provider/admin.sock"
+            }"#,
+        )
+        .expect("deser failed");
+        assert!(config.admin_listener().is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Mark and sweep garbage collection of blocks that are no longer reachable from the head, such
+//! as the entry chunks of abandoned builds.
+use crate::chain::{self, ChainError};
+use crate::store::IndexedStore;
+use forest_cid::Cid;
+use forest_db::Store;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct GcReport {
+    /// Blocks reachable from the head or a pending builder.
+    pub live: usize,
+    /// Blocks deleted.
+    pub swept: usize,
+    pub bytes_freed: u64,
+}
+
+/// Returns every block reachable from the head through `PreviousID`, `Entries` and `Next`, plus
+/// the entry chunks linked from `pending`, the `entries_link` of each builder not yet published.
+/// Entries in `pruned` are already gone and are skipped.
+pub(crate) fn mark<S: Store>(
+    bs: &IndexedStore<S>,
+    head: Option<Cid>,
+    pending: impl IntoIterator<Item = Cid>,
+    pruned: &HashSet<Cid>,
+) -> Result<HashSet<Cid>, ChainError> {
+    let mut live = HashSet::new();
+    for res in chain::ads(bs, head) {
+        let (cid, ad) = res?;
+        live.insert(cid);
+        live.extend(
+            chain::retained_entry_chunks(bs, cid, &ad, pruned)?
+                .into_iter()
+                .map(|(c, _)| c),
+        );
+    }
+    for first in pending {
+        live.extend(
+            chain::chunk_list(bs, Some(first))?
+                .into_iter()
+                .map(|(c, _)| c),
+        );
+    }
+    Ok(live)
+}
+
+/// Deletes every block that isn't in `live`. The caller must hold the blockstore exclusively
+/// between `mark` and `sweep`, so no new block is written in between.
+pub(crate) fn sweep<S: Store>(
+    bs: &IndexedStore<S>,
+    live: &HashSet<Cid>,
+) -> Result<GcReport, forest_db::Error> {
+    let mut report = GcReport {
+        live: live.len(),
+        ..GcReport::default()
+    };
+    for cid in bs.cids() {
+        if live.contains(&cid) {
+            continue;
+        }
+        let size = bs.block_size(&cid).unwrap_or(0);
+        bs.delete(cid.to_bytes())?;
+        report.swept += 1;
+        report.bytes_freed += size as u64;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advertisement::{Advertisement, EntryChunk, EntryChunkBuilder};
+    use forest_db::MemoryDB;
+    use forest_ipld::Ipld;
+    use ipld_blockstore::BlockStore;
+
+    fn chunk(bs: &IndexedStore<MemoryDB>, next: Option<Cid>, byte: u8) -> Cid {
+        match bs
+            .link_entries(next.map(Ipld::Link), vec![Ipld::Bytes(vec![byte])])
+            .unwrap()
+        {
+            Ipld::Link(cid) => cid,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_gc_keeps_reachable_and_pending() {
+        let bs = IndexedStore::new(MemoryDB::default());
+        let published = chunk(&bs, None, 1);
+        let ad = Advertisement {
+            Entries: Some(Ipld::Link(published)),
+            ..crate::tests::test_ad("gc")
+        };
+        let head = bs.put(&ad, forest_cid::Code::Blake2b256).unwrap();
+
+        let pending_tail = chunk(&bs, None, 2);
+        let pending = chunk(&bs, Some(pending_tail), 3);
+        let abandoned = chunk(&bs, None, 4);
+        let stray = bs
+            .put(
+                &EntryChunk {
+                    Entries: vec![],
+                    Next: Some(Ipld::Link(abandoned)),
+                },
+                forest_cid::Code::Blake2b256,
+            )
+            .unwrap();
+
+        let live = mark(&bs, Some(head), vec![pending], &HashSet::new()).unwrap();
+        let report = sweep(&bs, &live).unwrap();
+        assert_eq!(report.live, 4);
+        assert_eq!(report.swept, 2);
+        assert!(report.bytes_freed > 0);
+        assert_eq!(bs.block_count(), 4);
+        for cid in [head, published, pending, pending_tail] {
+            assert!(bs.get_bytes(&cid).unwrap().is_some());
+        }
+        for cid in [abandoned, stray] {
+            assert!(bs.get_bytes(&cid).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_mark_fails_on_missing_block() {
+        let bs = IndexedStore::new(MemoryDB::default());
+        let other = IndexedStore::new(MemoryDB::default());
+        let missing = chunk(&other, None, 1);
+        assert!(matches!(
+            mark(&bs, Some(missing), vec![], &HashSet::new()),
+            Err(ChainError::MissingBlock(_))
+        ));
+    }
+}
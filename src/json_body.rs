@@ -0,0 +1,232 @@
+//! JSON forms of the `/create` and `/adv/:id/entryChunk` bodies, for callers that can't easily
+//! produce dag-cbor. They are converted into the same structures as the dag-cbor bodies.
+use crate::advertisement::Advertisement;
+use crate::protobuf;
+use forest_cid::Cid;
+use forest_ipld::Ipld;
+use multihash::Multihash;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Multicodec code of the Bitswap retrieval protocol.
+const TRANSPORT_BITSWAP: u64 = 0x0900;
+/// Multicodec code of Filecoin retrieval over Graphsync.
+const TRANSPORT_GRAPHSYNC_FILECOINV1: u64 = 0x0910;
+
+#[derive(Debug, Error)]
+pub enum JsonBodyError {
+    #[error("Invalid JSON body: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid base64 in {0}: {1}")]
+    Base64(&'static str, base64::DecodeError),
+    #[error("Invalid multihash {0}")]
+    Multihash(String),
+    #[error("Invalid PieceCID {0}")]
+    PieceCid(String),
+}
+
+/// Bytes given either as text, which is used as is, or as `{"base64": "..."}`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum BytesJson {
+    Text(String),
+    Base64 { base64: String },
+}
+
+impl BytesJson {
+    fn into_bytes(self, field: &'static str) -> Result<Vec<u8>, JsonBodyError> {
+        match self {
+            BytesJson::Text(text) => Ok(text.into_bytes()),
+            BytesJson::Base64 { base64 } => {
+                base64::decode(base64).map_err(|e| JsonBodyError::Base64(field, e))
+            }
+        }
+    }
+}
+
+/// One retrieval protocol the entries can be fetched with.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "protocol", rename_all = "kebab-case")]
+enum MetadataJson {
+    Bitswap,
+    #[serde(rename = "graphsync-filecoinv1")]
+    GraphsyncFilecoinv1 {
+        #[serde(rename = "PieceCID")]
+        piece_cid: String,
+        #[serde(rename = "VerifiedDeal", default)]
+        verified_deal: bool,
+        #[serde(rename = "FastRetrieval", default)]
+        fast_retrieval: bool,
+    },
+    /// Metadata that is already encoded, for protocols not listed here.
+    Raw {
+        base64: String,
+    },
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+struct GraphsyncFilecoinV1 {
+    PieceCID: Cid,
+    VerifiedDeal: bool,
+    FastRetrieval: bool,
+}
+
+impl MetadataJson {
+    /// Appends the protocol's multicodec code, followed by its parameters if it has any.
+    fn encode(self, buf: &mut Vec<u8>) -> Result<(), JsonBodyError> {
+        match self {
+            MetadataJson::Bitswap => protobuf::put_varint(buf, TRANSPORT_BITSWAP),
+            MetadataJson::GraphsyncFilecoinv1 {
+                piece_cid,
+                verified_deal,
+                fast_retrieval,
+            } => {
+                let params = GraphsyncFilecoinV1 {
+                    PieceCID: Cid::try_from(piece_cid.as_str())
+                        .map_err(|_| JsonBodyError::PieceCid(piece_cid.clone()))?,
+                    VerifiedDeal: verified_deal,
+                    FastRetrieval: fast_retrieval,
+                };
+                protobuf::put_varint(buf, TRANSPORT_GRAPHSYNC_FILECOINV1);
+                buf.extend(forest_encoding::to_vec(&params).expect("params always encode"));
+            }
+            MetadataJson::Raw { base64 } => buf
+                .extend(base64::decode(base64).map_err(|e| JsonBodyError::Base64("Metadata", e))?),
+        }
+        Ok(())
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct AdvertisementJson {
+    Provider: String,
+    #[serde(default)]
+    Addresses: Vec<String>,
+    ContextID: BytesJson,
+    #[serde(default)]
+    Metadata: Vec<MetadataJson>,
+    #[serde(default)]
+    IsRm: bool,
+}
+
+/// Reads a `/create` body such as
+/// `{"Provider": "12D3Koo...", "Addresses": ["/ip4/.../tcp/3104"], "ContextID": "deal-1",
+/// "Metadata": [{"protocol": "bitswap"}]}`.
+pub(crate) fn advertisement(body: &[u8]) -> Result<Advertisement, JsonBodyError> {
+    let ad: AdvertisementJson = serde_json::from_slice(body)?;
+    let mut metadata = vec![];
+    for protocol in ad.Metadata {
+        protocol.encode(&mut metadata)?;
+    }
+    Ok(Advertisement {
+        PreviousID: None,
+        Provider: ad.Provider,
+        Addresses: ad.Addresses,
+        Signature: Ipld::Bytes(vec![]),
+        Entries: None,
+        ContextID: Ipld::Bytes(ad.ContextID.into_bytes("ContextID")?),
+        Metadata: Ipld::Bytes(metadata),
+        IsRm: ad.IsRm,
+    })
+}
+
+/// Reads an `/adv/:id/entryChunk` body, a list of base58 multihashes such as
+/// `["QmPZ9gcCEpqKTo6aq61g2nXGUhM4iCL3ewB6LDXZCtioEB"]`.
+pub(crate) fn entries(body: &[u8]) -> Result<Vec<Ipld>, JsonBodyError> {
+    let entries: Vec<String> = serde_json::from_slice(body)?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            bs58::decode(&entry)
+                .into_vec()
+                .ok()
+                .filter(|bytes| Multihash::from_bytes(bytes).is_ok())
+                .map(Ipld::Bytes)
+                .ok_or(JsonBodyError::Multihash(entry))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multihash::MultihashDigest;
+
+    #[test]
+    fn test_advertisement() {
+        let piece = "baga6ea4seaqao7s73y24kcutaosvacpdjgfe5pw76ooefnyqw4ynr3d2y6x2mpq";
+        let ad = advertisement(
+            format!(
+                r#"{{
+                    "Provider": "12D3KooWHHzSeKaY8xuZVzkLbKFfvNgPPeKhFBGrMbNzbm5akpqu",
+                    "Addresses": ["/ip4/127.0.0.1/tcp/9999"],
+                    "ContextID": {{"base64": "AAEC"}},
+                    "Metadata": [
+                        {{"protocol": "bitswap"}},
+                        {{"protocol": "graphsync-filecoinv1", "PieceCID": "{}", "VerifiedDeal": true}}
+                    ]
+                }}"#,
+                piece
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(ad.ContextID, Ipld::Bytes(vec![0, 1, 2]));
+        assert!(!ad.IsRm);
+        let metadata = match ad.Metadata {
+            Ipld::Bytes(bytes) => bytes,
+            _ => panic!("metadata is not bytes"),
+        };
+        // Bitswap, then Graphsync followed by its dag-cbor parameters.
+        assert_eq!(metadata[..4], [0x80, 0x12, 0x90, 0x12]);
+        let params: Ipld = forest_encoding::from_slice(&metadata[4..]).unwrap();
+        let params = match params {
+            Ipld::Map(params) => params,
+            _ => panic!("parameters are not a map"),
+        };
+        assert_eq!(
+            params["PieceCID"],
+            Ipld::Link(Cid::try_from(piece).unwrap())
+        );
+        assert_eq!(params["VerifiedDeal"], Ipld::Bool(true));
+        assert_eq!(params["FastRetrieval"], Ipld::Bool(false));
+
+        let removal = advertisement(
+            br#"{"Provider": "12D3KooWHHzSeKaY8xuZVzkLbKFfvNgPPeKhFBGrMbNzbm5akpqu", "ContextID": "deal-1", "IsRm": true}"#,
+        )
+        .unwrap();
+        assert_eq!(removal.ContextID, Ipld::Bytes(b"deal-1".to_vec()));
+        assert_eq!(removal.Metadata, Ipld::Bytes(vec![]));
+        assert!(removal.IsRm);
+
+        for bad in [
+            r#"{"ContextID": "deal-1"}"#,
+            r#"{"Provider": "p", "ContextID": {"base64": "!"}}"#,
+            r#"{"Provider": "p", "ContextID": "c", "Metadata": [{"protocol": "carrier-pigeon"}]}"#,
+            r#"{"Provider": "p", "ContextID": "c", "Metadata": [{"protocol": "graphsync-filecoinv1", "PieceCID": "nope"}]}"#,
+            r#"{"Provider": "p", "ContextID": "c", "Signature": "forged"}"#,
+        ] {
+            assert!(advertisement(bad.as_bytes()).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_entries() {
+        let mh = multihash::Code::Sha2_256.digest(b"entry");
+        let body = serde_json::to_vec(&[bs58::encode(mh.to_bytes()).into_string()]).unwrap();
+        assert_eq!(entries(&body).unwrap(), vec![Ipld::Bytes(mh.to_bytes())]);
+        assert!(matches!(
+            entries(br#"["0OIl"]"#),
+            Err(JsonBodyError::Multihash(_))
+        ));
+        // Valid base58, but not a multihash.
+        assert!(matches!(
+            entries(br#"["2g"]"#),
+            Err(JsonBodyError::Multihash(_))
+        ));
+        assert!(entries(br#"{"Entries": []}"#).is_err());
+    }
+}
@@ -0,0 +1,726 @@
+//! The publisher's signing keys: the current key, the keys it replaced, and a record of each
+//! rotation. Optionally persisted to a JSON key file.
+//!
+//! A rotation is announced in the chain with an ad signed by the outgoing key, whose metadata
+//! names the incoming key and carries the incoming key's signature over the outgoing peer id. An
+//! indexer that trusts the old key can follow the handover to the new one. IPNI has no message
+//! for this, so the announcement is an ordinary ad: it has no entries, so it adds nothing to the
+//! index, and its metadata is a single entry under a private use multicodec, which indexers
+//! that don't know the code keep without interpreting.
+//!
+//! Ads can instead be signed by the storage provider they advertise, with its own key, so one
+//! publisher can serve several providers. The publisher key still signs the head.
+//!
+//! Keys are stored the way go-libp2p stores them: a protobuf `PrivateKey` holding the raw
+//! Ed25519 or secp256k1 key, or a PKCS#1 RSA key. The libp2p version we build on only reads and
+//! writes Ed25519 keys in that form, so the others are handled here. ECDSA is out of scope until we
+//! move to libp2p 0.42 or later: 0.41 has no ECDSA key type, so neither a key nor a signature made
+//! by one could be represented, and such keys are rejected with `KeyError::Unsupported`.
+use crate::advertisement::Advertisement;
+use crate::protobuf::{self, Value};
+use crate::signer::{RemoteSigner, Signer};
+use forest_ipld::Ipld;
+use libp2p::identity::error::{DecodingError, SigningError};
+use libp2p::identity::{secp256k1, Keypair, PublicKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// ContextID of the ads that announce a key rotation.
+pub const ROTATION_CONTEXT_ID: &[u8] = b"/index-provider/key-rotation";
+
+/// Metadata protocol of a rotation statement, from the multicodec private use range.
+pub const ROTATION_PROTOCOL: u64 = 0x300000;
+
+#[derive(Error, Debug)]
+pub enum KeyError {
+    #[error("Failed to access key file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid key file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid key: {0}")]
+    Decoding(String),
+    #[error("{0} keys are not supported by this build of libp2p")]
+    Unsupported(&'static str),
+    #[error("Failed to sign: {0}")]
+    Signing(#[from] SigningError),
+    #[error("Not a key rotation announcement")]
+    NotARotation,
+    #[error("Key rotation proof does not verify")]
+    InvalidProof,
+    #[error("The key is held by a remote signer")]
+    Remote,
+}
+
+impl From<DecodingError> for KeyError {
+    fn from(e: DecodingError) -> Self {
+        KeyError::Decoding(e.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rotation {
+    /// Peer id of the retired key.
+    pub from: String,
+    /// Peer id of the new key.
+    pub to: String,
+    /// Unix time of the rotation, in seconds.
+    pub at: u64,
+    /// Cid of the ad announcing the rotation.
+    pub announcement: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    /// Protobuf encoded private keys, base64.
+    current: String,
+    #[serde(default)]
+    retired: Vec<String>,
+    #[serde(default)]
+    history: Vec<Rotation>,
+}
+
+/// Key types in the libp2p key protobuf.
+const KEY_TYPE_RSA: u64 = 0;
+const KEY_TYPE_ED25519: u64 = 1;
+const KEY_TYPE_SECP256K1: u64 = 2;
+const KEY_TYPE_ECDSA: u64 = 3;
+
+/// The type of newly generated keys. RSA keys can be loaded, but not generated.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    #[default]
+    Ed25519,
+    Secp256k1,
+}
+
+impl std::str::FromStr for KeyType {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, KeyError> {
+        match s {
+            "ed25519" => Ok(KeyType::Ed25519),
+            "secp256k1" => Ok(KeyType::Secp256k1),
+            _ => Err(KeyError::Decoding(format!("Unknown key type {}", s))),
+        }
+    }
+}
+
+impl KeyType {
+    pub fn generate(self) -> Keypair {
+        match self {
+            KeyType::Ed25519 => Keypair::generate_ed25519(),
+            KeyType::Secp256k1 => Keypair::generate_secp256k1(),
+        }
+    }
+}
+
+fn encode_key(key: &Keypair) -> Result<String, KeyError> {
+    let bytes = match key {
+        Keypair::Secp256k1(key) => {
+            let mut buf = vec![];
+            protobuf::put_uint(&mut buf, 1, KEY_TYPE_SECP256K1);
+            protobuf::put_bytes(&mut buf, 2, &key.secret().to_bytes());
+            buf
+        }
+        // Fails for RSA, which can't be exported once parsed.
+        key => key.to_protobuf_encoding()?,
+    };
+    Ok(base64::encode(bytes))
+}
+
+fn decode_key(key: &str) -> Result<Keypair, KeyError> {
+    let bytes = base64::decode(key).map_err(|e| KeyError::Decoding(e.to_string()))?;
+    let invalid = || KeyError::Decoding("malformed private key protobuf".into());
+    let fields = protobuf::read_fields(&bytes).ok_or_else(invalid)?;
+    let key_type = fields.iter().find_map(|field| match field {
+        (1, Value::Uint(key_type)) => Some(*key_type),
+        _ => None,
+    });
+    let mut data = fields
+        .iter()
+        .find_map(|field| match field {
+            (2, Value::Bytes(data)) => Some(data.to_vec()),
+            _ => None,
+        })
+        .ok_or_else(invalid)?;
+    // Proto2 writes the type even when it is zero, but be lenient.
+    match key_type.unwrap_or(KEY_TYPE_RSA) {
+        KEY_TYPE_ED25519 => Ok(Keypair::from_protobuf_encoding(&bytes)?),
+        KEY_TYPE_SECP256K1 => Ok(Keypair::Secp256k1(
+            secp256k1::SecretKey::from_bytes(&mut data)?.into(),
+        )),
+        KEY_TYPE_RSA => Ok(Keypair::rsa_from_pkcs8(&mut pkcs1_to_pkcs8(&data))?),
+        KEY_TYPE_ECDSA => Err(KeyError::Unsupported("ECDSA")),
+        other => Err(KeyError::Decoding(format!("unknown key type {}", other))),
+    }
+}
+
+fn der_header(tag: u8, len: usize) -> Vec<u8> {
+    let mut header = vec![tag];
+    if len < 0x80 {
+        header.push(len as u8);
+    } else {
+        let len_bytes: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        header.push(0x80 | len_bytes.len() as u8);
+        header.extend(len_bytes);
+    }
+    header
+}
+
+/// Wraps a PKCS#1 RSA private key, as go-libp2p stores them, in the PKCS#8 structure libp2p reads.
+fn pkcs1_to_pkcs8(pkcs1: &[u8]) -> Vec<u8> {
+    // Version 0, then the rsaEncryption algorithm identifier with NULL parameters.
+    const PREFIX: &[u8] = &[
+        0x02, 0x01, 0x00, 0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01,
+        0x01, 0x05, 0x00,
+    ];
+    let mut body = PREFIX.to_vec();
+    body.extend(der_header(0x04, pkcs1.len()));
+    body.extend_from_slice(pkcs1);
+    let mut der = der_header(0x30, body.len());
+    der.extend(body);
+    der
+}
+
+/// A key held in this process. RSA keys can't be encoded again once parsed, so the encoding a key
+/// was loaded from is kept for writing it back.
+struct LocalKey {
+    keypair: Keypair,
+    encoded: Option<String>,
+}
+
+impl LocalKey {
+    fn new(keypair: Keypair) -> Self {
+        LocalKey {
+            keypair,
+            encoded: None,
+        }
+    }
+
+    fn decode(encoded: &str) -> Result<Self, KeyError> {
+        Ok(LocalKey {
+            keypair: decode_key(encoded)?,
+            encoded: Some(encoded.to_string()),
+        })
+    }
+
+    fn encode(&self) -> Result<String, KeyError> {
+        match &self.encoded {
+            Some(encoded) => Ok(encoded.clone()),
+            None => encode_key(&self.keypair),
+        }
+    }
+}
+
+/// The current key, held in this process or by a remote signer.
+enum CurrentKey {
+    Local(Box<LocalKey>),
+    Remote(Arc<RemoteSigner>),
+}
+
+pub struct KeyRing {
+    current: CurrentKey,
+    retired: Vec<LocalKey>,
+    /// Type of the keys generated on rotation.
+    key_type: KeyType,
+    history: Vec<Rotation>,
+    path: Option<PathBuf>,
+}
+
+impl KeyRing {
+    /// A key ring that only lives in memory. Rotations generate keys of the same type as
+    /// `current`, or Ed25519 keys if it is an RSA key.
+    pub fn new(current: Keypair) -> Self {
+        let key_type = match current {
+            Keypair::Secp256k1(_) => KeyType::Secp256k1,
+            _ => KeyType::Ed25519,
+        };
+        KeyRing {
+            current: CurrentKey::Local(Box::new(LocalKey::new(current))),
+            retired: vec![],
+            key_type,
+            history: vec![],
+            path: None,
+        }
+    }
+
+    /// A key ring whose key is held by a remote signer. It can't be rotated.
+    pub fn remote(signer: RemoteSigner) -> Self {
+        KeyRing {
+            current: CurrentKey::Remote(Arc::new(signer)),
+            retired: vec![],
+            key_type: KeyType::default(),
+            history: vec![],
+            path: None,
+        }
+    }
+
+    /// Loads the key file at `path`, or creates it with a new key. Rotations generate keys of
+    /// `key_type`.
+    pub fn load_or_generate(path: impl AsRef<Path>, key_type: KeyType) -> Result<Self, KeyError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            let ring = KeyRing {
+                path: Some(path.into()),
+                key_type,
+                ..KeyRing::new(key_type.generate())
+            };
+            ring.save()?;
+            return Ok(ring);
+        }
+        Ok(KeyRing {
+            key_type,
+            ..Self::load(path)?
+        })
+    }
+
+    /// Loads the key file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KeyError> {
+        let path = path.as_ref();
+        let file: KeyFile = serde_json::from_slice(&std::fs::read(path)?)?;
+        Ok(KeyRing {
+            current: CurrentKey::Local(Box::new(LocalKey::decode(&file.current)?)),
+            retired: file
+                .retired
+                .iter()
+                .map(|k| LocalKey::decode(k))
+                .collect::<Result<_, _>>()?,
+            key_type: KeyType::default(),
+            history: file.history,
+            path: Some(path.into()),
+        })
+    }
+
+    /// Writes the key file, if there is one. Written to a temporary file first, so a crash can't
+    /// leave a truncated key file behind.
+    fn save(&self) -> Result<(), KeyError> {
+        let (path, current) = match (&self.path, &self.current) {
+            (Some(path), CurrentKey::Local(current)) => (path, current),
+            _ => return Ok(()),
+        };
+        let file = KeyFile {
+            current: current.encode()?,
+            retired: self
+                .retired
+                .iter()
+                .map(LocalKey::encode)
+                .collect::<Result<_, _>>()?,
+            history: self.history.clone(),
+        };
+        let tmp = path.with_extension("tmp");
+        {
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut f = options.open(&tmp)?;
+            std::io::Write::write_all(&mut f, &serde_json::to_vec_pretty(&file)?)?;
+            f.sync_all()?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Signs with the current key.
+    pub fn signer(&self) -> Arc<dyn Signer> {
+        match &self.current {
+            CurrentKey::Local(key) => Arc::new(key.keypair.clone()),
+            CurrentKey::Remote(signer) => signer.clone(),
+        }
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        self.key_type
+    }
+
+    pub fn is_remote(&self) -> bool {
+        matches!(self.current, CurrentKey::Remote(_))
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        PeerId::from_public_key(&self.signer().public())
+    }
+
+    pub fn history(&self) -> &[Rotation] {
+        &self.history
+    }
+
+    /// Peer ids of every key the ring has had, oldest first.
+    pub fn peer_ids(&self) -> Vec<PeerId> {
+        let mut ids = self.retired();
+        ids.push(self.peer_id());
+        ids
+    }
+
+    /// Peer ids of the keys that were rotated out.
+    pub fn retired(&self) -> Vec<PeerId> {
+        self.retired
+            .iter()
+            .map(|k| PeerId::from_public_key(&k.keypair.public()))
+            .collect()
+    }
+
+    /// Makes `new` the current key, and records the rotation along with the cid of the ad that
+    /// announced it.
+    pub fn rotate(
+        &mut self,
+        new: Keypair,
+        announcement: Option<String>,
+    ) -> Result<Rotation, KeyError> {
+        if self.is_remote() {
+            return Err(KeyError::Remote);
+        }
+        let rotation = Rotation {
+            from: self.peer_id().to_base58(),
+            to: PeerId::from_public_key(&new.public()).to_base58(),
+            at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            announcement,
+        };
+        if let CurrentKey::Local(old) = std::mem::replace(
+            &mut self.current,
+            CurrentKey::Local(Box::new(LocalKey::new(new))),
+        ) {
+            self.retired.push(*old);
+        }
+        self.history.push(rotation.clone());
+        if let Err(e) = self.save() {
+            // Keep memory and disk in agreement.
+            self.current = CurrentKey::Local(Box::new(self.retired.pop().unwrap()));
+            self.history.pop();
+            return Err(e);
+        }
+        Ok(rotation)
+    }
+}
+
+/// The signing keys of the storage providers this publisher advertises for, by peer id.
+#[derive(Default)]
+pub struct ProviderKeys {
+    keys: HashMap<String, Arc<dyn Signer>>,
+    /// Refuse ads for providers without a key, rather than signing them with the publisher key.
+    required: bool,
+}
+
+impl ProviderKeys {
+    pub fn new(required: bool) -> Self {
+        ProviderKeys {
+            keys: HashMap::new(),
+            required,
+        }
+    }
+
+    /// Loads the current key of each key file in `paths`.
+    pub fn load(paths: &[PathBuf], required: bool) -> Result<Self, KeyError> {
+        let mut keys = Self::new(required);
+        for path in paths {
+            keys.insert(KeyRing::load(path)?.signer());
+        }
+        Ok(keys)
+    }
+
+    /// Adds a provider's key and returns its peer id.
+    pub fn insert(&mut self, key: Arc<dyn Signer>) -> String {
+        let id = PeerId::from_public_key(&key.public()).to_base58();
+        self.keys.insert(id.clone(), key);
+        id
+    }
+
+    /// Peer ids of the providers with a key, sorted.
+    pub fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.keys.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// The key to sign an ad for `provider` with: the provider's own key if there is one,
+    /// otherwise the current publisher key. When provider keys are required, the publisher key
+    /// only signs for the ids its key ring has had. Retired keys never sign new ads, so ads for an
+    /// id from before a rotation are signed with the current key.
+    pub fn signing_key(&self, provider: &str, publisher: &KeyRing) -> Option<Arc<dyn Signer>> {
+        if let Some(key) = self.keys.get(provider) {
+            return Some(key.clone());
+        }
+        let own = publisher
+            .peer_ids()
+            .iter()
+            .any(|id| id.to_base58() == provider);
+        if own || !self.required {
+            Some(publisher.signer())
+        } else {
+            None
+        }
+    }
+}
+
+/// Metadata for the ad announcing a rotation from `old` to `new`: `ROTATION_PROTOCOL`, followed by
+/// a dag-cbor map of the new public key and the new key's signature over the old peer id. The ad
+/// itself is signed with `old`.
+pub(crate) fn rotation_metadata(old_id: &PeerId, new: &Keypair) -> Result<Ipld, KeyError> {
+    let mut statement = BTreeMap::new();
+    statement.insert(
+        "NewKey".to_string(),
+        Ipld::Bytes(new.public().to_protobuf_encoding()),
+    );
+    statement.insert(
+        "Proof".to_string(),
+        Ipld::Bytes(new.sign(&old_id.to_bytes())?),
+    );
+    let mut bytes = vec![];
+    protobuf::put_varint(&mut bytes, ROTATION_PROTOCOL);
+    bytes.extend(
+        forest_encoding::to_vec(&Ipld::Map(statement))
+            .map_err(|e| KeyError::Decoding(e.to_string()))?,
+    );
+    Ok(Ipld::Bytes(bytes))
+}
+
+/// Checks a rotation announcement and returns the key it hands over to. The caller must still
+/// check the ad's own signature, made by the outgoing key.
+pub fn verify_rotation(ad: &Advertisement, old: &PeerId) -> Result<PublicKey, KeyError> {
+    if ad.ContextID != Ipld::Bytes(ROTATION_CONTEXT_ID.to_vec()) {
+        return Err(KeyError::NotARotation);
+    }
+    let statement: Ipld = match &ad.Metadata {
+        Ipld::Bytes(bytes) => {
+            let mut rest = &bytes[..];
+            if protobuf::read_varint(&mut rest) != Some(ROTATION_PROTOCOL) {
+                return Err(KeyError::NotARotation);
+            }
+            forest_encoding::from_slice(rest).map_err(|_| KeyError::NotARotation)?
+        }
+        _ => return Err(KeyError::NotARotation),
+    };
+    let field = |name: &str| match &statement {
+        Ipld::Map(map) => match map.get(name) {
+            Some(Ipld::Bytes(bytes)) => Ok(bytes.clone()),
+            _ => Err(KeyError::NotARotation),
+        },
+        _ => Err(KeyError::NotARotation),
+    };
+    let new = PublicKey::from_protobuf_encoding(&field("NewKey")?)?;
+    if !new.verify(&old.to_bytes(), &field("Proof")?) {
+        return Err(KeyError::InvalidProof);
+    }
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_persists() {
+        let path = std::env::temp_dir().join(format!("keys-{:016x}.json", rand::random::<u64>()));
+        let mut ring = KeyRing::load_or_generate(&path, KeyType::Ed25519).unwrap();
+        let first = ring.signer().public();
+        assert_eq!(
+            KeyRing::load_or_generate(&path, KeyType::Ed25519)
+                .unwrap()
+                .signer()
+                .public(),
+            first
+        );
+
+        let rotation = ring
+            .rotate(Keypair::generate_ed25519(), Some("bafy".into()))
+            .unwrap();
+        assert_eq!(rotation.from, PeerId::from_public_key(&first).to_base58());
+
+        let reloaded = KeyRing::load_or_generate(&path, KeyType::Ed25519).unwrap();
+        assert_eq!(reloaded.signer().public(), ring.signer().public());
+        assert_eq!(reloaded.retired(), vec![PeerId::from_public_key(&first)]);
+        assert_eq!(reloaded.history(), &[rotation]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Keys in go-libp2p's encoding, from the vectors importer-helper/cmd/interop-vectors writes.
+    fn vector_keys() -> Vec<(String, String)> {
+        [
+            include_str!("../testdata/interop-secp256k1.json"),
+            include_str!("../testdata/interop-rsa.json"),
+        ]
+        .iter()
+        .map(|vector| {
+            let vector: serde_json::Value = serde_json::from_str(vector).unwrap();
+            (
+                vector["key_file"]["current"].as_str().unwrap().to_string(),
+                vector["peer_id"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_load_go_keys() {
+        for (key, peer_id) in vector_keys() {
+            let path =
+                std::env::temp_dir().join(format!("keys-{:016x}.json", rand::random::<u64>()));
+            std::fs::write(&path, serde_json::json!({ "current": key }).to_string()).unwrap();
+            let mut ring = KeyRing::load_or_generate(&path, KeyType::Ed25519).unwrap();
+            assert_eq!(ring.peer_id().to_base58(), peer_id);
+
+            // RSA keys can't be encoded again, so the loaded encoding is what gets written back.
+            ring.rotate(KeyType::Secp256k1.generate(), None).unwrap();
+            let reloaded = KeyRing::load(&path).unwrap();
+            assert_eq!(reloaded.retired()[0].to_base58(), peer_id);
+            assert_eq!(reloaded.peer_id(), ring.peer_id());
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        let mut ecdsa = vec![];
+        protobuf::put_uint(&mut ecdsa, 1, KEY_TYPE_ECDSA);
+        protobuf::put_bytes(&mut ecdsa, 2, &[1; 32]);
+        assert!(matches!(
+            decode_key(&base64::encode(ecdsa)),
+            Err(KeyError::Unsupported("ECDSA"))
+        ));
+    }
+
+    #[test]
+    fn test_sign_with_each_key_type() {
+        async_std::task::block_on(async {
+            let mut keys = vec![KeyType::Ed25519.generate(), KeyType::Secp256k1.generate()];
+            keys.extend(
+                vector_keys()
+                    .iter()
+                    .map(|(key, _)| decode_key(key).unwrap()),
+            );
+            // A generated secp256k1 key survives the key file.
+            let secp256k1 = decode_key(&encode_key(&keys[1]).unwrap()).unwrap();
+            assert_eq!(secp256k1.public(), keys[1].public());
+
+            let cid = forest_cid::Cid::try_from(
+                "bafybeicyhbhhklw3kdwgrxmf67mhkgjbsjauphsvrzywav63kn7bkpmqfa",
+            )
+            .unwrap();
+            for key in keys {
+                let (pk, head) = crate::signed_head::SignedHead::new(&key, cid, None)
+                    .await
+                    .unwrap()
+                    .open()
+                    .unwrap();
+                assert_eq!((pk, head), (key.public(), cid));
+
+                let ad = crate::advertisement::AdvertisementBuilder {
+                    entries_link: None,
+                    entry_count: 0,
+                    ad: Advertisement {
+                        PreviousID: None,
+                        Provider: PeerId::from_public_key(&key.public()).to_base58(),
+                        Addresses: vec![],
+                        Signature: Ipld::Bytes(vec![]),
+                        Entries: None,
+                        ContextID: Ipld::Bytes(b"ctx".to_vec()),
+                        Metadata: Ipld::Bytes(vec![]),
+                        IsRm: false,
+                    },
+                }
+                .build(&key)
+                .await
+                .unwrap();
+                ad.verify_sig().unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn test_remote_key_is_not_rotated() {
+        async_std::task::block_on(async {
+            let key = Keypair::generate_ed25519();
+            let signer = crate::signer::tests::remote_signer(key.clone()).await;
+            let mut ring = KeyRing::remote(signer);
+            assert_eq!(ring.peer_id(), PeerId::from_public_key(&key.public()));
+            assert!(matches!(
+                ring.rotate(Keypair::generate_ed25519(), None),
+                Err(KeyError::Remote)
+            ));
+            assert_eq!(ring.peer_id(), PeerId::from_public_key(&key.public()));
+        })
+    }
+
+    #[test]
+    fn test_signing_key() {
+        let mut publisher = KeyRing::new(Keypair::generate_ed25519());
+        let publisher_id = publisher.peer_id().to_base58();
+        let mut keys = ProviderKeys::new(true);
+        let provider = keys.insert(Arc::new(Keypair::generate_ed25519()));
+        assert_eq!(keys.ids(), vec![provider.clone()]);
+
+        let signer = keys.signing_key(&provider, &publisher).unwrap();
+        assert_eq!(
+            PeerId::from_public_key(&signer.public()).to_base58(),
+            provider
+        );
+        let signer = keys.signing_key(&publisher_id, &publisher).unwrap();
+        assert_eq!(signer.public(), publisher.signer().public());
+        assert!(keys.signing_key("unknown", &publisher).is_none());
+
+        // Ads for the publisher's old id are signed by the new key, never the retired one.
+        let old = publisher.signer().public();
+        publisher.rotate(Keypair::generate_ed25519(), None).unwrap();
+        let signer = keys.signing_key(&publisher_id, &publisher).unwrap();
+        assert_ne!(signer.public(), old);
+        assert_eq!(signer.public(), publisher.signer().public());
+        let new_id = publisher.peer_id().to_base58();
+        let signer = keys.signing_key(&new_id, &publisher).unwrap();
+        assert_eq!(signer.public(), publisher.signer().public());
+
+        keys.required = false;
+        let signer = keys.signing_key("unknown", &publisher).unwrap();
+        assert_eq!(signer.public(), publisher.signer().public());
+    }
+
+    #[test]
+    fn test_verify_rotation() {
+        let old = Keypair::generate_ed25519();
+        let new = Keypair::generate_ed25519();
+        let old_id = PeerId::from_public_key(&old.public());
+        let mut ad = Advertisement {
+            PreviousID: None,
+            Provider: old_id.to_base58(),
+            Addresses: vec![],
+            Signature: Ipld::Bytes(vec![]),
+            Entries: None,
+            ContextID: Ipld::Bytes(ROTATION_CONTEXT_ID.to_vec()),
+            Metadata: rotation_metadata(&old_id, &new).unwrap(),
+            IsRm: false,
+        };
+        assert_eq!(verify_rotation(&ad, &old_id).unwrap(), new.public());
+
+        // The proof is bound to the outgoing key.
+        let other = PeerId::from_public_key(&Keypair::generate_ed25519().public());
+        assert!(matches!(
+            verify_rotation(&ad, &other),
+            Err(KeyError::InvalidProof)
+        ));
+        ad.ContextID = Ipld::Bytes(b"other".to_vec());
+        assert!(matches!(
+            verify_rotation(&ad, &old_id),
+            Err(KeyError::NotARotation)
+        ));
+
+        // The statement is tagged with its metadata protocol.
+        ad.ContextID = Ipld::Bytes(ROTATION_CONTEXT_ID.to_vec());
+        if let Ipld::Bytes(metadata) = &mut ad.Metadata {
+            metadata[0] ^= 1;
+        }
+        assert!(matches!(
+            verify_rotation(&ad, &old_id),
+            Err(KeyError::NotARotation)
+        ));
+    }
+}
@@ -0,0 +1,2234 @@
+//! An HTTP index provider: serves a signed advertisement chain to indexers, with an admin API to
+//! build it. The binary runs the server and the operator commands; the clients for both APIs are
+//! exported for indexers and tools that embed them.
+pub mod advertisement;
+mod auth;
+mod car;
+pub mod chain;
+pub mod cli;
+pub mod client;
+mod compact;
+pub mod config;
+mod gc;
+mod json_body;
+mod keys;
+mod limits;
+mod logging;
+mod metrics;
+mod openapi;
+mod protobuf;
+mod prune;
+mod shutdown;
+pub mod signed_head;
+pub mod signer;
+mod store;
+pub mod sync;
+mod tls;
+pub mod transport;
+pub mod verify;
+
+use advertisement::{Advertisement, AdvertisementBuilder};
+use async_std::{
+    self,
+    sync::{Arc, RwLock},
+};
+use auth::Scope;
+use config::Config;
+use forest_cid::Cid;
+use forest_db::{MemoryDB, Store};
+use forest_ipld::Ipld;
+use ipld_blockstore::BlockStore;
+use libp2p::futures::future::{join, select, Either};
+use limits::{BodyLimit, BodyLimits};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use signed_head::SignedHead;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use store::{IndexedStore, Namespaced};
+use tide::http::headers::{CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use tide::StatusCode;
+use tide::{self, Body, Response};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument, Span};
+
+/// Blocks are addressed by their cid, so they can be cached forever.
+const BLOCK_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// The head changes on every publish, so it may only be cached briefly.
+const HEAD_CACHE_CONTROL: &str = "public, max-age=5";
+
+fn etag(cid: &Cid) -> String {
+    format!("\"{}\"", cid)
+}
+
+/// Returns true if the request's `If-None-Match` header matches the given etag.
+fn if_none_match<State>(req: &tide::Request<State>, etag: &str) -> bool {
+    req.header(IF_NONE_MATCH)
+        .map(|values| {
+            values
+                .iter()
+                .flat_map(|v| v.as_str().split(','))
+                .map(|tag| tag.trim())
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        })
+        .unwrap_or(false)
+}
+
+fn not_modified(etag: &str, cache_control: &str) -> Response {
+    Response::builder(StatusCode::NotModified)
+        .header(ETAG, etag)
+        .header(CACHE_CONTROL, cache_control)
+        .build()
+}
+
+async fn head<BS>(r: tide::Request<Provider<BS>>) -> tide::Result<Response> {
+    r.state().metrics.head_requests.inc();
+    let head = r.state().head.read().await;
+    // Read under the head lock, so the seq matches the head.
+    let seq = r.state().head_seq.load(Ordering::SeqCst);
+    if let Some(head) = *head {
+        let etag = etag(&head);
+        if if_none_match(&r, &etag) {
+            return Ok(not_modified(&etag, HEAD_CACHE_CONTROL));
+        }
+        let signer = r.state().keys.read().await.signer();
+        let seq = Some(seq).filter(|_| r.state().extended_head);
+        let signed_head = SignedHead::new(&*signer, head, seq).await?;
+        Ok(Response::builder(StatusCode::Ok)
+            .body(Body::from_json(&signed_head)?)
+            .header(ETAG, etag.as_str())
+            .header(CACHE_CONTROL, HEAD_CACHE_CONTROL)
+            .build())
+    } else {
+        tide::Result::Err(tide::Error::from_str(tide::StatusCode::NotFound, "No head"))
+    }
+}
+
+async fn block<BS: BlockStore>(req: tide::Request<Provider<BS>>) -> tide::Result<Response> {
+    req.state().metrics.block_fetches.inc();
+    let cid: Cid = req.param("cid")?.parse()?;
+    if req.state().pruned_chunks.read().await.contains(&cid) {
+        // Tell indexers the entries are gone for good, rather than just missing from this store.
+        req.state().metrics.block_not_found.inc();
+        return Ok(Response::builder(StatusCode::NotFound)
+            .body(ENTRIES_UNAVAILABLE)
+            .build());
+    }
+    let bs = req.state().blockstore.read().await;
+    // The store's error is not `Send`, so only keep its message across the awaits below.
+    let res = bs.get_bytes(&cid).map_err(|e| e.to_string());
+    match res {
+        Ok(Some(bytes)) => {
+            let etag = etag(&cid);
+            if if_none_match(&req, &etag) {
+                return Ok(not_modified(&etag, BLOCK_CACHE_CONTROL));
+            }
+            // Blocks are immutable, so each one only needs to be checked the first time it is
+            // served. Ad signatures are only checked when `verify_on_serve` is set.
+            if !req.state().verified_blocks.read().await.contains(&cid) {
+                let verified = verify::verify_block(&cid, &bytes).and_then(|_| {
+                    if req.state().verify_ads {
+                        verify::verify_ad_block(&bytes)
+                    } else {
+                        Ok(())
+                    }
+                });
+                if let Err(e) = verified {
+                    error!(%cid, error = %e, "Refusing to serve corrupt block");
+                    return Err(tide::Error::from_str(
+                        tide::StatusCode::InternalServerError,
+                        format!("block {} failed verification", cid),
+                    ));
+                }
+                req.state().verified_blocks.write().await.insert(cid);
+            }
+            let mut body = Body::from_bytes(bytes);
+            if cid.codec() == forest_cid::DAG_CBOR {
+                body.set_mime(DAG_CBOR_CONTENT_TYPE);
+            }
+            Ok(Response::builder(StatusCode::Ok)
+                .body(body)
+                .header(ETAG, etag.as_str())
+                .header(CACHE_CONTROL, BLOCK_CACHE_CONTROL)
+                .build())
+        }
+        Ok(None) => {
+            req.state().metrics.block_not_found.inc();
+            tide::Result::Err(tide::Error::from_str(
+                tide::StatusCode::NotFound,
+                "block not found",
+            ))?
+        }
+        Err(e) => tide::Result::Err(tide::Error::from_str(
+            tide::StatusCode::InternalServerError,
+            e,
+        ))?,
+    }
+}
+
+const ENTRIES_UNAVAILABLE: &str = "Entries are no longer available";
+const DAG_CBOR_CONTENT_TYPE: &str = "application/vnd.ipld.dag-cbor";
+
+/// The routes of the public app. `mount_public` and the OpenAPI document both go through this
+/// table, so every mounted route is documented.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum PublicRoute {
+    Head,
+    Block,
+}
+
+impl PublicRoute {
+    pub(crate) const ALL: [PublicRoute; 2] = [PublicRoute::Head, PublicRoute::Block];
+
+    /// The path in tide's syntax, relative to the layout it is mounted in.
+    pub(crate) fn path(self) -> &'static str {
+        match self {
+            PublicRoute::Head => "/head",
+            PublicRoute::Block => "/:cid",
+        }
+    }
+}
+
+/// The original layout (`/head`, `/<cid>`) and the IPNI layout (`/ipni/v1/ad/head`,
+/// `/ipni/v1/ad/<cid>`), both served under the public prefix.
+pub(crate) const PUBLIC_LAYOUTS: [&str; 2] = ["", "/ipni/v1/ad"];
+
+/// The routes of the admin app, shared by `mount_admin` and the OpenAPI document like
+/// `PublicRoute`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum AdminRoute {
+    Create,
+    EntryChunk,
+    Publish,
+    Export,
+    Import,
+    Fsck,
+    Gc,
+    Compact,
+    Keys,
+    RotateKey,
+    Metrics,
+    OpenApi,
+}
+
+impl AdminRoute {
+    pub(crate) const ALL: [AdminRoute; 12] = [
+        AdminRoute::Create,
+        AdminRoute::EntryChunk,
+        AdminRoute::Publish,
+        AdminRoute::Export,
+        AdminRoute::Import,
+        AdminRoute::Fsck,
+        AdminRoute::Gc,
+        AdminRoute::Compact,
+        AdminRoute::Keys,
+        AdminRoute::RotateKey,
+        AdminRoute::Metrics,
+        AdminRoute::OpenApi,
+    ];
+
+    /// The path in tide's syntax.
+    pub(crate) fn path(self) -> &'static str {
+        match self {
+            AdminRoute::Create => "/create",
+            AdminRoute::EntryChunk => "/adv/:id/entryChunk",
+            AdminRoute::Publish => "/adv/:id/publish",
+            AdminRoute::Export => "/export",
+            AdminRoute::Import => "/import",
+            AdminRoute::Fsck => "/fsck",
+            AdminRoute::Gc => "/gc",
+            AdminRoute::Compact => "/compact",
+            AdminRoute::Keys => "/keys",
+            AdminRoute::RotateKey => "/keys/rotate",
+            AdminRoute::Metrics => "/metrics",
+            AdminRoute::OpenApi => "/openapi.json",
+        }
+    }
+}
+
+/// Mounts the public routes under `prefix`, in every layout.
+fn mount_public<BS>(app: &mut tide::Server<Provider<BS>>, prefix: &str)
+where
+    BS: BlockStore + Clone + Send + Sync + 'static,
+{
+    let prefix = prefix.trim_end_matches('/');
+    for layout in PUBLIC_LAYOUTS {
+        for route in PublicRoute::ALL {
+            let mut at = app.at(&format!("{}{}{}", prefix, layout, route.path()));
+            match route {
+                PublicRoute::Head => at.get(head),
+                PublicRoute::Block => at.get(block),
+            };
+        }
+    }
+}
+
+/// Mounts the admin routes, each limited to its configured body size.
+fn mount_admin<S>(app: &mut tide::Server<Provider<IndexedStore<S>>>, body_limits: &BodyLimits)
+where
+    S: Store + Clone + Send + Sync + 'static,
+{
+    let openapi = openapi::document(body_limits);
+    for route in AdminRoute::ALL {
+        let mut at = app.at(route.path());
+        match route {
+            AdminRoute::Create => at.with(BodyLimit(body_limits.create)).post(create),
+            AdminRoute::EntryChunk => at.with(BodyLimit(body_limits.entry_chunk)).post(add_chunk),
+            AdminRoute::Publish => at.with(BodyLimit(body_limits.publish)).post(publish_ad),
+            AdminRoute::Export => at.get(export),
+            AdminRoute::Import => at.with(BodyLimit(body_limits.import)).post(import),
+            AdminRoute::Fsck => at.get(fsck),
+            AdminRoute::Gc => at.post(gc),
+            AdminRoute::Compact => at.post(compact),
+            AdminRoute::Keys => at.get(list_keys),
+            AdminRoute::RotateKey => at.post(rotate_key),
+            AdminRoute::Metrics => at.get(metrics),
+            AdminRoute::OpenApi => {
+                let openapi = openapi.clone();
+                at.get(move |_: tide::Request<Provider<IndexedStore<S>>>| {
+                    let openapi = openapi.clone();
+                    async move { Body::from_json(&openapi) }
+                })
+            }
+        };
+    }
+}
+
+/// Mounts another chain under `/p/<peer id>` on both apps. It is mounted under every peer id its
+/// key ring has had, so indexers that knew it by an earlier key still find it.
+fn mount_tenant<S, State>(
+    app: &mut tide::Server<State>,
+    admin_app: &mut tide::Server<State>,
+    tenant: Provider<IndexedStore<S>>,
+    ids: &[String],
+    prefix: &str,
+    body_limits: &BodyLimits,
+) where
+    S: Store + Clone + Send + Sync + 'static,
+    State: Clone + Send + Sync + 'static,
+{
+    let prefix = prefix.trim_end_matches('/');
+    for id in ids {
+        let mut public = tide::with_state(tenant.clone());
+        mount_public(&mut public, "");
+        app.at(&format!("{}/p/{}", prefix, id)).nest(public);
+        let mut admin = tide::with_state(tenant.clone());
+        mount_admin(&mut admin, body_limits);
+        admin_app.at(&format!("/p/{}", id)).nest(admin);
+    }
+}
+
+/// Whether the body is one of the JSON forms from `json_body` rather than dag-cbor.
+fn is_json<State>(r: &tide::Request<State>) -> bool {
+    r.content_type()
+        .is_some_and(|mime| mime.essence() == "application/json")
+}
+
+fn bad_request(e: json_body::JsonBodyError) -> tide::Error {
+    tide::Error::from_str(StatusCode::BadRequest, e.to_string())
+}
+
+#[instrument(skip_all, fields(temp_ad_id))]
+async fn create<BS>(mut r: tide::Request<Provider<BS>>) -> tide::Result<Value> {
+    let id: i64 = rand::thread_rng().gen();
+    Span::current().record("temp_ad_id", id);
+    let body = r.body_bytes().await?;
+    let ad: Advertisement = if is_json(&r) {
+        json_body::advertisement(&body).map_err(bad_request)?
+    } else {
+        forest_encoding::from_slice(&body)?
+    };
+    auth::require(
+        &r,
+        if ad.IsRm {
+            Scope::Remove
+        } else {
+            Scope::Create
+        },
+    )?;
+    if r.state()
+        .provider_keys
+        .signing_key(&ad.Provider, &*r.state().keys.read().await)
+        .is_none()
+    {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("No signing key for provider {}", ad.Provider),
+        ));
+    }
+    let builder = AdvertisementBuilder {
+        ad,
+        entries_link: None,
+        entry_count: 0,
+    };
+
+    let mut temp_ads = r.state().temp_ads.write().await;
+    temp_ads.insert(id, builder);
+    r.state().metrics.ads_created.inc();
+    info!("Created temporary advertisement");
+
+    Ok(id.into())
+}
+
+#[instrument(skip_all, fields(temp_ad_id))]
+async fn add_chunk<BS: BlockStore>(mut r: tide::Request<Provider<BS>>) -> tide::Result<StatusCode> {
+    auth::require(&r, Scope::Create)?;
+    let id: i64 = r.param("id")?.parse()?;
+    Span::current().record("temp_ad_id", id);
+    let body = r.body_bytes().await?;
+    let entries: Vec<Ipld> = if is_json(&r) {
+        json_body::entries(&body).map_err(bad_request)?
+    } else {
+        forest_encoding::from_slice(&body)?
+    };
+    let mut temp_ads = r.state().temp_ads.write().await;
+    if let Some(ad_builder) = temp_ads.get_mut(&id) {
+        let bs = r.state().blockstore.write().await;
+        ad_builder.link_entries(&*bs, entries).map_err(|e| {
+            tide::Error::from_str(tide::StatusCode::InternalServerError, format!("{}", e))
+        })?;
+        r.state().metrics.entry_chunks_added.inc();
+        debug!(entries = ad_builder.entry_count, "Added entry chunk");
+        return Ok(StatusCode::Ok);
+    }
+
+    tide::Result::Err(tide::Error::from_str(
+        tide::StatusCode::NotFound,
+        "Temporary ad not found from given id",
+    ))
+}
+
+#[instrument(skip_all, fields(temp_ad_id, cid))]
+async fn publish_ad<BS: BlockStore>(r: tide::Request<Provider<BS>>) -> tide::Result<String> {
+    auth::require(&r, Scope::Publish)?;
+    let start = Instant::now();
+    let id: i64 = r.param("id")?.parse()?;
+    Span::current().record("temp_ad_id", id);
+    let mut head = r.state().head.write().await;
+    let keys = r.state().keys.read().await;
+    let mut temp_ads = r.state().temp_ads.write().await;
+    if let Some(mut ad_builder) = temp_ads.remove(&id) {
+        // The head is signed by the publisher, the ad by the provider it advertises.
+        let keypair = match r
+            .state()
+            .provider_keys
+            .signing_key(&ad_builder.ad.Provider, &keys)
+        {
+            Some(keypair) => keypair,
+            None => {
+                let msg = format!("No signing key for provider {}", ad_builder.ad.Provider);
+                temp_ads.insert(id, ad_builder);
+                return Err(tide::Error::from_str(StatusCode::BadRequest, msg));
+            }
+        };
+        let bs = r.state().blockstore.write().await;
+        // PreviousID is part of the signed payload, so it has to be set before building.
+        ad_builder.ad.PreviousID = (*head).map(forest_ipld::Ipld::Link);
+        let entry_count = ad_builder.entry_count;
+        let is_rm = ad_builder.ad.IsRm;
+        let ad = ad_builder.build(&*keypair).await?;
+        let ipld_node = forest_ipld::to_ipld(ad)?;
+
+        let cid = bs
+            .put(&ipld_node, forest_cid::Code::Blake2b256)
+            .map_err(|e| {
+                tide::Error::from_str(tide::StatusCode::InternalServerError, format!("{}", e))
+            })?;
+        Span::current().record("cid", cid.to_string());
+        *head = Some(cid);
+        r.state().bump_head_seq();
+        if is_rm {
+            r.state()
+                .removals
+                .write()
+                .await
+                .insert(cid, SystemTime::now());
+        }
+
+        let metrics = &r.state().metrics;
+        metrics.ads_published.inc();
+        metrics.entries_per_ad.observe(entry_count as f64);
+        metrics
+            .publish_latency_seconds
+            .observe(start.elapsed().as_secs_f64());
+        info!(entries = entry_count, "Published advertisement");
+        return Ok(cid.to_string());
+    }
+
+    tide::Result::Err(tide::Error::from_str(
+        tide::StatusCode::NotFound,
+        "Temporary ad not found",
+    ))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    until: Option<String>,
+}
+
+fn raw_block<BS: BlockStore>(bs: &BS, cid: Cid) -> tide::Result<(Cid, Vec<u8>)> {
+    match bs.get_bytes(&cid) {
+        Ok(Some(bytes)) => Ok((cid, bytes)),
+        Ok(None) => Err(tide::Error::from_str(
+            tide::StatusCode::InternalServerError,
+            format!("block {} is missing from the blockstore", cid),
+        )),
+        Err(e) => Err(tide::Error::from_str(
+            tide::StatusCode::InternalServerError,
+            format!("{}", e),
+        )),
+    }
+}
+
+/// Exports the chain from the head back to `until` (inclusive), along with the entry chunks of
+/// each ad, as a CARv1 rooted at the head.
+async fn export<BS: BlockStore>(r: tide::Request<Provider<BS>>) -> tide::Result<Response> {
+    auth::require(&r, Scope::Read)?;
+    let query: ExportQuery = r.query()?;
+    let until: Option<Cid> = query.until.map(|c| c.parse()).transpose()?;
+    let head = match *r.state().head.read().await {
+        Some(head) => head,
+        None => return Err(tide::Error::from_str(tide::StatusCode::NotFound, "No head")),
+    };
+
+    let bs = r.state().blockstore.read().await;
+    let pruned = r.state().pruned_chunks.read().await;
+    let mut blocks = vec![];
+    let mut found_until = false;
+    for res in chain::ads(&*bs, Some(head)) {
+        let (cid, ad) = res?;
+        blocks.push(raw_block(&*bs, cid)?);
+        for (chunk_cid, _) in chain::retained_entry_chunks(&*bs, cid, &ad, &pruned)? {
+            blocks.push(raw_block(&*bs, chunk_cid)?);
+        }
+        if Some(cid) == until {
+            found_until = true;
+            break;
+        }
+    }
+    if until.is_some() && !found_until {
+        return Err(tide::Error::from_str(
+            tide::StatusCode::NotFound,
+            "Given cid is not part of the chain",
+        ));
+    }
+
+    Ok(Response::builder(StatusCode::Ok)
+        .body(car::write_car(head, blocks))
+        .content_type(car::CAR_CONTENT_TYPE)
+        .build())
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    /// Replace an existing chain with an unrelated, complete one.
+    #[serde(default)]
+    replace: bool,
+}
+
+/// Loads a CAR produced by `export` into the blockstore and makes its root the head. The CAR must
+/// hold a complete chain, or one whose oldest ad follows the current head. A complete chain only
+/// replaces an existing one with `?replace=true`. Every ad signature is checked, and only blocks
+/// reachable from the root are written, before the head moves.
+async fn import<BS: BlockStore>(mut r: tide::Request<Provider<BS>>) -> tide::Result<String> {
+    auth::require(&r, Scope::Publish)?;
+    let query: ImportQuery = r.query()?;
+    let bad_request = |e: &dyn std::fmt::Display| {
+        tide::Error::from_str(tide::StatusCode::BadRequest, format!("{}", e))
+    };
+    let (roots, blocks) = car::read_car(&r.body_bytes().await?).map_err(|e| bad_request(&e))?;
+    let root = match roots.as_slice() {
+        [root] => *root,
+        _ => return Err(bad_request(&"CAR must have exactly one root")),
+    };
+
+    let staging = MemoryDB::default();
+    for (cid, bytes) in &blocks {
+        staging.write(cid.to_bytes(), bytes)?;
+    }
+    // Hold the head until the import is done, so nothing is published on top of the old one.
+    let mut head = r.state().head.write().await;
+    let mut reachable = HashSet::new();
+    let mut removals = vec![];
+    let mut pruned = vec![];
+    let mut extends_head = false;
+    for res in chain::ads(&staging, Some(root)) {
+        let (cid, ad) = match res {
+            Ok((cid, _)) if Some(cid) == *head => {
+                extends_head = true;
+                break;
+            }
+            Ok(res) => res,
+            // A partial export has to pick up where this chain leaves off.
+            Err(chain::ChainError::MissingBlock(missing)) if Some(missing) == *head => {
+                extends_head = true;
+                break;
+            }
+            Err(chain::ChainError::MissingBlock(missing)) => {
+                return Err(bad_request(&format!(
+                    "CAR is not a complete chain and does not follow the head, {} is missing",
+                    missing
+                )))
+            }
+            Err(e) => return Err(bad_request(&e)),
+        };
+        ad.verify_sig()
+            .map_err(|e| bad_request(&chain::ChainError::BadSignature(cid, e)))?;
+        reachable.insert(cid);
+        match chain::entry_chunks(&staging, cid, &ad) {
+            Ok(chunks) => reachable.extend(chunks.into_iter().map(|(chunk_cid, _)| chunk_cid)),
+            // Entries that were pruned before the export are left out of it entirely.
+            Err(chain::ChainError::MissingBlock(missing))
+                if chain::link(&ad.Entries, cid).ok().flatten() == Some(missing) =>
+            {
+                pruned.push(missing)
+            }
+            Err(e) => return Err(bad_request(&e)),
+        }
+        if ad.IsRm {
+            removals.push(cid);
+        }
+    }
+    if head.is_some() && !extends_head && !query.replace {
+        return Err(tide::Error::from_str(
+            tide::StatusCode::Conflict,
+            "CAR does not follow the current head, pass replace=true to replace the chain",
+        ));
+    }
+
+    let bs = r.state().blockstore.write().await;
+    for (cid, bytes) in blocks {
+        if reachable.contains(&cid) {
+            bs.write(cid.to_bytes(), bytes)?;
+        }
+    }
+    if !extends_head {
+        if let Some(old) = *head {
+            warn!(cid = %old, "Replacing the chain with an imported one");
+        }
+    }
+    *head = Some(root);
+    r.state().bump_head_seq();
+    r.state().pruned_chunks.write().await.extend(pruned);
+    // The original removal times aren't in the chain, so the grace period starts now.
+    let now = SystemTime::now();
+    r.state()
+        .removals
+        .write()
+        .await
+        .extend(removals.into_iter().map(|cid| (cid, now)));
+    info!(cid = %root, "Imported chain");
+    Ok(root.to_string())
+}
+
+/// Verifies every block reachable from the head.
+async fn fsck<BS: BlockStore>(r: tide::Request<Provider<BS>>) -> tide::Result<Body> {
+    auth::require(&r, Scope::Read)?;
+    let head = *r.state().head.read().await;
+    let bs = r.state().blockstore.read().await;
+    let pruned = r.state().pruned_chunks.read().await;
+    Body::from_json(&verify::fsck(&*bs, head, &pruned))
+}
+
+async fn metrics<S: Store>(r: tide::Request<Provider<IndexedStore<S>>>) -> tide::Result<Response> {
+    auth::require(&r, Scope::Read)?;
+    let pending_builders = r.state().temp_ads.read().await.len();
+    let bs = r.state().blockstore.read().await;
+    let body = r.state().metrics.render(&metrics::Gauges {
+        pending_builders,
+        blockstore_blocks: bs.block_count(),
+        blockstore_bytes: bs.total_bytes(),
+    });
+    Ok(Response::builder(StatusCode::Ok)
+        .body(body)
+        .content_type(metrics::METRICS_CONTENT_TYPE)
+        .build())
+}
+
+/// Runs a GC pass. Holds the head, the pending builders and the blockstore for the whole pass, so
+/// nothing can be published or linked between mark and sweep.
+async fn collect_garbage<S: Store>(
+    provider: &Provider<IndexedStore<S>>,
+) -> Result<gc::GcReport, String> {
+    let head = provider.head.write().await;
+    let temp_ads = provider.temp_ads.read().await;
+    let bs = provider.blockstore.write().await;
+    let pending = temp_ads
+        .values()
+        .filter_map(|builder| match &builder.entries_link {
+            Some(Ipld::Link(cid)) => Some(*cid),
+            _ => None,
+        });
+    let pruned = provider.pruned_chunks.read().await;
+    let live = gc::mark(&*bs, *head, pending, &pruned).map_err(|e| e.to_string())?;
+    let report = gc::sweep(&*bs, &live).map_err(|e| e.to_string())?;
+    provider
+        .verified_blocks
+        .write()
+        .await
+        .retain(|cid| live.contains(cid));
+    provider.metrics.gc_blocks_swept.inc_by(report.swept as u64);
+    Ok(report)
+}
+
+/// Deletes the entry chunks of contexts removed more than `grace_period` ago. Chunks that pending
+/// builders link to are kept, since an unpublished ad may share them.
+async fn prune_removed<S: Store>(
+    provider: &Provider<IndexedStore<S>>,
+    grace_period: Duration,
+) -> Result<prune::PruneReport, String> {
+    let head = provider.head.write().await;
+    let temp_ads = provider.temp_ads.read().await;
+    let bs = provider.blockstore.write().await;
+    let mut pruned = provider.pruned_chunks.write().await;
+    let removals = provider.removals.read().await;
+    let now = SystemTime::now();
+    let expired = |cid: &Cid| match removals.get(cid) {
+        Some(at) => now.duration_since(*at).unwrap_or_default() >= grace_period,
+        None => false,
+    };
+    let mut chunks = prune::prunable(&*bs, *head, &pruned, expired).map_err(|e| e.to_string())?;
+    for builder in temp_ads.values() {
+        if let Some(Ipld::Link(first)) = &builder.entries_link {
+            for (cid, _) in chain::chunk_list(&*bs, Some(*first)).map_err(|e| e.to_string())? {
+                chunks.remove(&cid);
+            }
+        }
+    }
+
+    let mut report = prune::PruneReport::default();
+    for cid in chunks {
+        report.bytes_freed += bs.block_size(&cid).unwrap_or(0) as u64;
+        report.chunks += 1;
+        bs.delete(cid.to_bytes()).map_err(|e| e.to_string())?;
+        pruned.insert(cid);
+    }
+    provider
+        .metrics
+        .entry_chunks_pruned
+        .inc_by(report.chunks as u64);
+    Ok(report)
+}
+
+/// Republishes the live ads as a new chain and makes it the head. The ads keep their provider,
+/// addresses, metadata and entries, and are signed again since `PreviousID` changes.
+/// Replaces the chain with one holding only the ads `compact::live_ads` keeps. A chain that would
+/// be left empty is kept as it is, since an empty head looks like a provider that never published.
+async fn compact_chain<BS: BlockStore>(
+    provider: &Provider<BS>,
+) -> tide::Result<compact::CompactReport> {
+    let internal = |e: &dyn std::fmt::Display| {
+        tide::Error::from_str(tide::StatusCode::InternalServerError, e.to_string())
+    };
+    let mut head = provider.head.write().await;
+    let keys = provider.keys.read().await;
+    let bs = provider.blockstore.write().await;
+    let (kept, ads_before) = compact::live_ads(&*bs, *head).map_err(|e| internal(&e))?;
+    if kept.is_empty() && ads_before > 0 {
+        return Err(tide::Error::from_str(
+            tide::StatusCode::Conflict,
+            "Compacting would leave the chain empty",
+        ));
+    }
+
+    let mut new_head = None;
+    for (_, ad) in kept.iter() {
+        let builder = AdvertisementBuilder {
+            ad: Advertisement {
+                PreviousID: new_head.map(Ipld::Link),
+                Provider: ad.Provider.clone(),
+                Addresses: ad.Addresses.clone(),
+                Signature: Ipld::Bytes(vec![]),
+                Entries: None,
+                ContextID: ad.ContextID.clone(),
+                Metadata: ad.Metadata.clone(),
+                IsRm: ad.IsRm,
+            },
+            entries_link: ad.Entries.clone(),
+            entry_count: 0,
+        };
+        let keypair = provider
+            .provider_keys
+            .signing_key(&ad.Provider, &keys)
+            .ok_or_else(|| internal(&format!("No signing key for provider {}", ad.Provider)))?;
+        let ad = builder.build(&*keypair).await.map_err(|e| internal(&e))?;
+        new_head = Some(
+            bs.put(&ad, forest_cid::Code::Blake2b256)
+                .map_err(|e| internal(&e))?,
+        );
+    }
+    *head = new_head;
+    provider.bump_head_seq();
+    // The ads the removals removed were dropped, so their entries are gone and there is nothing
+    // left to prune.
+    provider.removals.write().await.clear();
+    Ok(compact::CompactReport {
+        head: new_head.map(|cid| cid.to_string()),
+        ads_before,
+        ads_after: kept.len(),
+        gc: None,
+    })
+}
+
+#[derive(Deserialize)]
+struct CompactQuery {
+    /// Collect the blocks of the old chain straight away.
+    #[serde(default)]
+    gc: bool,
+}
+
+/// Replaces the chain with one holding only the live ads.
+async fn compact<S: Store>(r: tide::Request<Provider<IndexedStore<S>>>) -> tide::Result<Body> {
+    auth::require(&r, Scope::Publish)?;
+    let query: CompactQuery = r.query()?;
+    let mut report = compact_chain(r.state()).await?;
+    let internal = |e: String| tide::Error::from_str(tide::StatusCode::InternalServerError, e);
+    info!(
+        cid = report.head.as_deref().unwrap_or_default(),
+        ads_before = report.ads_before,
+        ads_after = report.ads_after,
+        "Compacted chain"
+    );
+    if query.gc {
+        report.gc = Some(collect_garbage(r.state()).await.map_err(internal)?);
+    }
+    Body::from_json(&report)
+}
+
+#[derive(Serialize)]
+struct KeysInfo<'a> {
+    current: String,
+    retired: Vec<String>,
+    history: &'a [keys::Rotation],
+    /// Storage providers whose ads are signed with their own key.
+    providers: Vec<String>,
+}
+
+/// Lists the current and retired publisher keys, and the rotations between them.
+async fn list_keys<BS>(r: tide::Request<Provider<BS>>) -> tide::Result<Body> {
+    auth::require(&r, Scope::Read)?;
+    let keys = r.state().keys.read().await;
+    Body::from_json(&KeysInfo {
+        current: keys.peer_id().to_base58(),
+        retired: keys.retired().iter().map(|id| id.to_base58()).collect(),
+        history: keys.history(),
+        providers: r.state().provider_keys.ids(),
+    })
+}
+
+/// Switches to a newly generated key. The switch is announced with an ad signed by the old key
+/// that names the new one, so indexers that trust the old key can follow it.
+async fn rotate_key<BS: BlockStore>(r: tide::Request<Provider<BS>>) -> tide::Result<Body> {
+    auth::require(&r, Scope::Keys)?;
+    let internal = |e: &dyn std::fmt::Display| {
+        tide::Error::from_str(StatusCode::InternalServerError, e.to_string())
+    };
+    let mut head = r.state().head.write().await;
+    let mut keys = r.state().keys.write().await;
+    let bs = r.state().blockstore.write().await;
+
+    if keys.is_remote() {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "The publisher key is held by a remote signer and can't be rotated",
+        ));
+    }
+    let old = keys.signer();
+    let new = keys.key_type().generate();
+    let announcement = AdvertisementBuilder {
+        ad: Advertisement {
+            PreviousID: (*head).map(Ipld::Link),
+            Provider: keys.peer_id().to_base58(),
+            Addresses: vec![],
+            Signature: Ipld::Bytes(vec![]),
+            Entries: None,
+            ContextID: Ipld::Bytes(keys::ROTATION_CONTEXT_ID.to_vec()),
+            Metadata: keys::rotation_metadata(&keys.peer_id(), &new).map_err(|e| internal(&e))?,
+            IsRm: false,
+        },
+        entries_link: None,
+        entry_count: 0,
+    }
+    .build(&*old)
+    .await?;
+    // Check the handover before committing to it, since indexers can't follow a bad one.
+    keys::verify_rotation(&announcement, &keys.peer_id()).map_err(|e| internal(&e))?;
+    let cid = bs
+        .put(&announcement, forest_cid::Code::Blake2b256)
+        .map_err(|e| internal(&e))?;
+
+    let rotation = keys
+        .rotate(new, Some(cid.to_string()))
+        .map_err(|e| internal(&e))?;
+    *head = Some(cid);
+    r.state().bump_head_seq();
+    info!(
+        from = %rotation.from,
+        to = %rotation.to,
+        %cid,
+        "Rotated publisher key"
+    );
+    Body::from_json(&rotation)
+}
+
+/// Deletes blocks that aren't reachable from the head or a pending builder.
+async fn gc<S: Store>(r: tide::Request<Provider<IndexedStore<S>>>) -> tide::Result<Body> {
+    auth::require(&r, Scope::Publish)?;
+    let report = collect_garbage(r.state())
+        .await
+        .map_err(|e| tide::Error::from_str(tide::StatusCode::InternalServerError, e))?;
+    info!(
+        swept = report.swept,
+        bytes_freed = report.bytes_freed,
+        "Collected garbage"
+    );
+    Body::from_json(&report)
+}
+
+#[derive(Clone)]
+struct Provider<BS> {
+    head: Arc<RwLock<Option<Cid>>>,
+    /// Goes up every time the head changes. Signed into extended heads. See `bump_head_seq`.
+    head_seq: Arc<AtomicU64>,
+    /// Serve extended heads, with a seq and timestamp, instead of legacy ones.
+    extended_head: bool,
+    keys: Arc<RwLock<keys::KeyRing>>,
+    /// Keys of the storage providers whose ads this publisher signs.
+    provider_keys: Arc<keys::ProviderKeys>,
+    blockstore: Arc<RwLock<BS>>,
+    temp_ads: Arc<RwLock<HashMap<i64, AdvertisementBuilder>>>,
+    /// Blocks that have already been checked against their cid by `block`.
+    verified_blocks: Arc<RwLock<HashSet<Cid>>>,
+    /// Also check the signature of ads before serving them.
+    verify_ads: bool,
+    /// When each removal ad was published, to time the grace period before pruning.
+    removals: Arc<RwLock<HashMap<Cid, SystemTime>>>,
+    /// Entry chunks deleted by pruning, which are reported as no longer available.
+    pruned_chunks: Arc<RwLock<HashSet<Cid>>>,
+    metrics: Arc<metrics::Metrics>,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl<BS> Provider<BS> {
+    fn new(blockstore: BS, keys: keys::KeyRing) -> Self {
+        Provider {
+            blockstore: Arc::new(RwLock::new(blockstore)),
+            head: Arc::new(RwLock::new(None)),
+            head_seq: Arc::new(AtomicU64::new(unix_millis())),
+            extended_head: false,
+            keys: Arc::new(RwLock::new(keys)),
+            provider_keys: Arc::new(keys::ProviderKeys::default()),
+            temp_ads: Arc::new(RwLock::new(HashMap::new())),
+            verified_blocks: Arc::new(RwLock::new(HashSet::new())),
+            verify_ads: false,
+            removals: Arc::new(RwLock::new(HashMap::new())),
+            pruned_chunks: Arc::new(RwLock::new(HashSet::new())),
+            metrics: Arc::new(metrics::Metrics::default()),
+        }
+    }
+
+    /// Moves the seq on for a new head. Nothing is persisted, so the seq is the time of the change
+    /// in milliseconds since the unix epoch, and keeps going up across restarts as long as the
+    /// clock does. Two changes within a millisecond still get distinct seqs.
+    fn bump_head_seq(&self) {
+        let now = unix_millis();
+        let _ = self
+            .head_seq
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |seq| {
+                Some(now.max(seq + 1))
+            });
+    }
+
+    fn with_provider_keys(self, provider_keys: keys::ProviderKeys) -> Self {
+        Provider {
+            provider_keys: Arc::new(provider_keys),
+            ..self
+        }
+    }
+
+    fn with_verify_ads(self, verify_ads: bool) -> Self {
+        Provider { verify_ads, ..self }
+    }
+
+    fn with_extended_head(self, extended_head: bool) -> Self {
+        Provider {
+            extended_head,
+            ..self
+        }
+    }
+}
+
+/// Serves the app on `addr`, over TLS when configured.
+async fn serve<State: Clone + Send + Sync + 'static>(
+    app: tide::Server<State>,
+    addr: String,
+    tls: Option<tls::TlsConfig>,
+) -> std::io::Result<()> {
+    match tls {
+        Some(tls) => tls::listen(app, addr, tls).await,
+        None => app.listen(addr).await,
+    }
+}
+
+/// Runs the provider described by `config` until it is told to shut down.
+pub fn run(config: Config) -> Result<(), std::io::Error> {
+    logging::init().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let admin_listener = config.admin_listener()?;
+    let invalid_key = |e: &dyn std::fmt::Display| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+    };
+    let keys = match (&config.signer, &config.key_path) {
+        (Some(remote), _) => async_std::task::block_on(signer::RemoteSigner::connect(remote))
+            .map(keys::KeyRing::remote)
+            .map_err(|e| invalid_key(&e))?,
+        (None, Some(path)) => {
+            keys::KeyRing::load_or_generate(path, config.key_type).map_err(|e| invalid_key(&e))?
+        }
+        (None, None) => keys::KeyRing::new(config.key_type.generate()),
+    };
+    let mut provider_keys =
+        keys::ProviderKeys::load(&config.provider_key_paths, config.require_provider_key)
+            .map_err(|e| invalid_key(&e))?;
+    for remote in &config.provider_signers {
+        let remote = async_std::task::block_on(signer::RemoteSigner::connect(remote))
+            .map_err(|e| invalid_key(&e))?;
+        provider_keys.insert(Arc::new(remote));
+    }
+    // Every chain gets its own namespace in the one database. The tenants' namespaces are named
+    // after their first key, so they stay put across rotations.
+    let db = Arc::new(MemoryDB::default());
+    let provider = Provider::new(IndexedStore::new(Namespaced::new(db.clone(), "")), keys)
+        .with_provider_keys(provider_keys)
+        .with_verify_ads(config.verify_on_serve)
+        .with_extended_head(config.extended_head);
+    let mut tenants = vec![];
+    for tenant in &config.tenants {
+        let keys = keys::KeyRing::load_or_generate(&tenant.key_path, config.key_type)
+            .map_err(|e| invalid_key(&e))?;
+        let ids: Vec<String> = keys.peer_ids().iter().map(|id| id.to_base58()).collect();
+        let store = IndexedStore::new(Namespaced::new(db.clone(), format!("/p/{}/", ids[0])));
+        // A tenant only signs ads for itself.
+        let tenant = Provider::new(store, keys)
+            .with_provider_keys(keys::ProviderKeys::new(true))
+            .with_verify_ads(config.verify_on_serve)
+            .with_extended_head(config.extended_head);
+        tenants.push((ids, tenant));
+    }
+    let chains: Vec<_> = std::iter::once(provider.clone())
+        .chain(tenants.iter().map(|(_, tenant)| tenant.clone()))
+        .collect();
+    let mut app = tide::with_state(provider.clone());
+    let mut admin_app = tide::with_state(provider.clone());
+
+    async_std::task::block_on(async {
+        app.with(logging::request_log);
+        if let Some(rate_limit) = config.rate_limit.clone() {
+            app.with(limits::RateLimit::new(rate_limit));
+        }
+        mount_public(&mut app, &config.path_prefix);
+        admin_app.with(logging::request_log);
+        let admin_auth = auth::Auth::new(config.admin_tokens.clone());
+        if !admin_auth.is_enabled() {
+            warn!("No admin_tokens configured, the admin API is unauthenticated");
+        }
+        admin_app.with(admin_auth);
+        let drain = shutdown::Drain::default();
+        admin_app.with(drain.clone());
+        mount_admin(&mut admin_app, &config.body_limits);
+        for (ids, tenant) in tenants {
+            mount_tenant(
+                &mut app,
+                &mut admin_app,
+                tenant,
+                &ids,
+                &config.path_prefix,
+                &config.body_limits,
+            );
+        }
+
+        for chain in chains.iter() {
+            let publisher_id = chain.keys.read().await.peer_id().to_base58();
+            info!(
+                %publisher_id,
+                provider_ids = %chain.provider_keys.ids().join(","),
+                "Starting provider"
+            );
+        }
+
+        if let Some(interval) = config.gc_interval_secs {
+            let chains = chains.clone();
+            async_std::task::spawn(async move {
+                loop {
+                    async_std::task::sleep(Duration::from_secs(interval)).await;
+                    for chain in chains.iter() {
+                        let publisher_id = chain.keys.read().await.peer_id().to_base58();
+                        let span = info_span!("gc", %publisher_id);
+                        match collect_garbage(chain).instrument(span.clone()).await {
+                            Ok(report) => info!(
+                                parent: &span,
+                                swept = report.swept,
+                                bytes_freed = report.bytes_freed,
+                                "Collected garbage"
+                            ),
+                            Err(e) => {
+                                error!(parent: &span, error = %e, "Garbage collection failed")
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        if let Some(prune) = config.prune.clone() {
+            let chains = chains.clone();
+            async_std::task::spawn(async move {
+                let grace_period = Duration::from_secs(prune.grace_period_secs);
+                loop {
+                    async_std::task::sleep(Duration::from_secs(prune.interval_secs)).await;
+                    for chain in chains.iter() {
+                        let publisher_id = chain.keys.read().await.peer_id().to_base58();
+                        let span = info_span!("prune", %publisher_id);
+                        match prune_removed(chain, grace_period)
+                            .instrument(span.clone())
+                            .await
+                        {
+                            Ok(report) => info!(
+                                parent: &span,
+                                chunks = report.chunks,
+                                bytes_freed = report.bytes_freed,
+                                "Pruned entries of removed contexts"
+                            ),
+                            Err(e) => error!(parent: &span, error = %e, "Pruning failed"),
+                        }
+                    }
+                }
+            });
+        }
+
+        let servers = join(
+            serve(app, config.listen_addr.clone(), config.tls.clone()),
+            serve(admin_app, admin_listener, config.admin_tls.clone()),
+        );
+        let signal = match select(Box::pin(servers), Box::pin(shutdown::signal())).await {
+            Either::Left(((app_res, admin_res), _)) => {
+                app_res.expect("failed to start server");
+                admin_res.expect("failed to start admin server");
+                return;
+            }
+            Either::Right((signal, _)) => signal.expect("failed to wait for signals"),
+        };
+
+        // The listeners were dropped along with the `select`, so no new connections are accepted.
+        info!(signal, "Shutting down");
+        let timeout = Duration::from_secs(config.drain_timeout_secs);
+        if !drain.drain(timeout).await {
+            warn!(
+                in_flight = drain.in_flight(),
+                "Admin requests still running after the drain timeout"
+            );
+        }
+        // Nothing is flushed: the blockstore and head live in memory and go with the process.
+        let head = *provider.head.read().await;
+        if let Some(path) = &config.admin_unix_socket {
+            let _ = std::fs::remove_file(path);
+        }
+        info!(
+            head = %head.map(|h| h.to_string()).unwrap_or_default(),
+            "Shutdown complete"
+        );
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use forest_encoding::from_slice;
+    use forest_ipld::Ipld;
+    use libp2p::identity::Keypair;
+    use multihash::MultihashDigest;
+    use tide::utils::After;
+    use tide_testing::TideTestingExt;
+
+    #[test]
+    fn test_create_ad() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let provider = Provider::new(
+                MemoryDB::default(),
+                keys::KeyRing::new(Keypair::generate_ed25519()),
+            );
+
+            let mut app = tide::with_state(provider.clone());
+            app.at("/head").get(head);
+            app.at("/:cid").get(block);
+            app.at("/create").post(create);
+            app.at("/adv/:id/entryChunk").post(add_chunk);
+            app.at("/adv/:id/publish").post(publish_ad);
+            app.with(auth::Auth::new(vec![]));
+
+            app.with(After(|res: Response| async {
+                if let Some(err) = res.error() {
+                    println!("Server error: {:?}", err)
+                }
+                Ok(res)
+            }));
+
+            // We didn't pass anythign in so this should fail
+            assert_eq!(
+                app.post("/create").send().await?.status(),
+                tide::StatusCode::InternalServerError
+            );
+
+            // Create an advertisement
+            let ad = Advertisement {
+                PreviousID: None,
+                Provider: "12D3KooWHHzSeKaY8xuZVzkLbKFfvNgPPeKhFBGrMbNzbm5akpqu".into(),
+                Addresses: vec!["/ip4/127.0.0.1/tcp/9999".into()],
+                Signature: Ipld::Bytes(vec![]),
+                Entries: None,
+                Metadata: Ipld::Bytes(vec![]),
+                ContextID: Ipld::Bytes("some-context".into()),
+                IsRm: false,
+            };
+            let ad_bytes = forest_encoding::to_vec(&ad)?;
+
+            let mut resp = app.post("/create").body_bytes(ad_bytes).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+            let id = resp.body_string().await?.parse::<i64>().unwrap();
+            println!("id {:?}", id);
+
+            // Put entries in that advertisement
+            let mut entries: Vec<Ipld> = vec![];
+            let count = 10;
+
+            for i in 0..count {
+                let b = Into::<i32>::into(i).to_ne_bytes();
+                let mh = multihash::Code::Blake2b256.digest(&b);
+                entries.push(Ipld::Bytes(mh.to_bytes()))
+            }
+
+            let entries_bytes = forest_encoding::to_vec(&entries)?;
+
+            let resp = app
+                .post(format!("/adv/{}/entryChunk", id))
+                .body_bytes(entries_bytes)
+                .send()
+                .await?;
+
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            // Publish the advertisement
+            let mut resp = app.post(format!("/adv/{}/publish", id)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+            let published_ad_cid = forest_cid::Cid::from_str(&resp.body_string().await?)?;
+
+            // Check that the head has been updated
+            let signed_head: SignedHead = app.get("/head").recv_json().await?;
+            assert_eq!(signed_head.open()?.1, published_ad_cid);
+
+            // Check that we can fetch the advertisement via http
+            let mut resp = app.get(format!("/{}", published_ad_cid)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            let ad_bytes = resp.body_bytes().await?;
+            let ad: Advertisement = from_slice(&ad_bytes)?;
+            println!("ad {:?}", ad);
+
+            Ok(())
+        })
+    }
+
+    pub(crate) type TestStore = IndexedStore<MemoryDB>;
+
+    pub(crate) fn test_provider() -> Provider<TestStore> {
+        Provider::new(
+            IndexedStore::new(MemoryDB::default()),
+            keys::KeyRing::new(Keypair::generate_ed25519()),
+        )
+    }
+
+    pub(crate) fn test_app(provider: Provider<TestStore>) -> tide::Server<Provider<TestStore>> {
+        let mut app = tide::with_state(provider);
+        app.with(auth::Auth::new(vec![]));
+        mount_public(&mut app, "");
+        mount_admin(&mut app, &BodyLimits::default());
+        app
+    }
+
+    /// The provider the test ads are published for.
+    pub(crate) const TEST_PEER_ID: &str = "12D3KooWHHzSeKaY8xuZVzkLbKFfvNgPPeKhFBGrMbNzbm5akpqu";
+
+    /// An unsigned ad for `context`, with no entries and no previous ad.
+    pub(crate) fn test_ad(context: &str) -> Advertisement {
+        Advertisement {
+            PreviousID: None,
+            Provider: TEST_PEER_ID.into(),
+            Addresses: vec!["/ip4/127.0.0.1/tcp/9999".into()],
+            Signature: Ipld::Bytes(vec![]),
+            Entries: None,
+            Metadata: Ipld::Bytes(vec![]),
+            ContextID: Ipld::Bytes(context.into()),
+            IsRm: false,
+        }
+    }
+
+    /// Stores `ad` after `previous` without signing it, with the hashes of `entries`, if any, in
+    /// one entry chunk.
+    pub(crate) fn put_test_ad<BS: BlockStore>(
+        bs: &BS,
+        previous: Option<Cid>,
+        mut ad: Advertisement,
+        entries: &[&str],
+    ) -> Cid {
+        if !entries.is_empty() {
+            let chunk = advertisement::EntryChunk {
+                Entries: entries
+                    .iter()
+                    .map(|e| {
+                        Ipld::Bytes(multihash::Code::Blake2b256.digest(e.as_bytes()).to_bytes())
+                    })
+                    .collect(),
+                Next: None,
+            };
+            ad.Entries = Some(Ipld::Link(
+                bs.put(&chunk, forest_cid::Code::Blake2b256).unwrap(),
+            ));
+        }
+        ad.PreviousID = previous.map(Ipld::Link);
+        bs.put(&ad, forest_cid::Code::Blake2b256).unwrap()
+    }
+
+    pub(crate) async fn publish_test_ad(
+        app: &tide::Server<Provider<TestStore>>,
+        context: &str,
+    ) -> Result<Cid, Box<dyn std::error::Error>> {
+        let id = app
+            .post("/create")
+            .body_bytes(forest_encoding::to_vec(&test_ad(context))?)
+            .recv_string()
+            .await?;
+
+        let mh = multihash::Code::Blake2b256.digest(context.as_bytes());
+        let entries_bytes = forest_encoding::to_vec(&vec![Ipld::Bytes(mh.to_bytes())])?;
+        let resp = app
+            .post(format!("/adv/{}/entryChunk", id))
+            .body_bytes(entries_bytes)
+            .send()
+            .await?;
+        assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+        let cid = app
+            .post(format!("/adv/{}/publish", id))
+            .recv_string()
+            .await?;
+        Ok(Cid::from_str(&cid)?)
+    }
+
+    #[test]
+    fn test_export_import() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider());
+            let first = publish_test_ad(&app, "first").await?;
+            let other = test_app(test_provider());
+            let full = app.get("/export").recv_bytes().await?;
+            let imported = other.post("/import").body_bytes(full).recv_string().await?;
+            assert_eq!(imported, first.to_string());
+
+            let second = publish_test_ad(&app, "second").await?;
+            let third = publish_test_ad(&app, "third").await?;
+            // Export only the two latest ads.
+            let car_bytes = app
+                .get(format!("/export?until={}", second))
+                .recv_bytes()
+                .await?;
+            let (roots, blocks) = car::read_car(&car_bytes)?;
+            assert_eq!(roots, vec![third]);
+            // Two ads, each with one entry chunk.
+            assert_eq!(blocks.len(), 4);
+            assert!(blocks.iter().all(|(cid, _)| *cid != first));
+
+            // A partial chain is only accepted on top of the ad it follows.
+            let fresh = test_app(test_provider());
+            let resp = fresh
+                .post("/import")
+                .body_bytes(car_bytes.clone())
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::BadRequest);
+            let resp = fresh.get("/head").send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::NotFound);
+
+            // Blocks the root doesn't reach are left out.
+            let stray = MemoryDB::default();
+            let stray_cid = stray.put(&"stray", forest_cid::Code::Blake2b256)?;
+            let (_, mut blocks) = car::read_car(&car_bytes)?;
+            blocks.push(raw_block(&stray, stray_cid)?);
+            let imported = other
+                .post("/import")
+                .body_bytes(car::write_car(third, blocks))
+                .recv_string()
+                .await?;
+            assert_eq!(imported, third.to_string());
+            let signed_head: SignedHead = other.get("/head").recv_json().await?;
+            assert_eq!(signed_head.open()?.1, third);
+            assert!(other
+                .state()
+                .blockstore
+                .read()
+                .await
+                .get_bytes(&stray_cid)?
+                .is_none());
+            let report: verify::FsckReport = other.get("/fsck").recv_json().await?;
+            assert!(report.errors.is_empty(), "{:?}", report.errors);
+            assert_eq!(report.checked, 6);
+
+            // An unrelated chain only replaces the current one when asked to.
+            let unrelated = test_app(test_provider());
+            let unrelated_head = publish_test_ad(&unrelated, "unrelated").await?;
+            let unrelated_car = unrelated.get("/export").recv_bytes().await?;
+            let resp = other
+                .post("/import")
+                .body_bytes(unrelated_car.clone())
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Conflict);
+            let imported = other
+                .post("/import?replace=true")
+                .body_bytes(unrelated_car)
+                .recv_string()
+                .await?;
+            assert_eq!(imported, unrelated_head.to_string());
+
+            // A CAR with a tampered ad signature is rejected.
+            let bs = MemoryDB::default();
+            let mut ad: Advertisement = app.state().blockstore.read().await.get(&third)?.unwrap();
+            ad.Metadata = Ipld::Bytes("tampered".into());
+            let tampered = bs.put(&ad, forest_cid::Code::Blake2b256)?;
+            let tampered_car = car::write_car(tampered, vec![raw_block(&bs, tampered)?]);
+            let resp = other
+                .post("/import")
+                .body_bytes(tampered_car)
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::BadRequest);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_ipni_paths() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let provider = test_provider();
+            let app = test_app(provider.clone());
+            let cid = publish_test_ad(&app, "ipni").await?;
+
+            let mut prefixed = tide::with_state(provider);
+            mount_public(&mut prefixed, "/provider/");
+
+            for (app, base) in [
+                (&app, ""),
+                (&app, "/ipni/v1/ad"),
+                (&prefixed, "/provider"),
+                (&prefixed, "/provider/ipni/v1/ad"),
+            ] {
+                let mut resp = app.get(format!("{}/head", base)).send().await?;
+                assert_eq!(resp.status(), tide::StatusCode::Ok);
+                assert_eq!(resp.content_type(), Some(tide::http::mime::JSON));
+                let signed_head: SignedHead = resp.body_json().await?;
+                assert_eq!(signed_head.open()?.1, cid);
+
+                let mut resp = app.get(format!("{}/{}", base, cid)).send().await?;
+                assert_eq!(resp.status(), tide::StatusCode::Ok);
+                assert_eq!(
+                    resp.content_type().map(|m| m.essence().to_string()),
+                    Some(DAG_CBOR_CONTENT_TYPE.to_string())
+                );
+                let _: Advertisement = from_slice(&resp.body_bytes().await?)?;
+            }
+
+            // The unprefixed layout is not served when a prefix is configured.
+            assert_eq!(
+                prefixed.get("/head").send().await?.status(),
+                tide::StatusCode::NotFound
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_gc() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider());
+            let head = publish_test_ad(&app, "published").await?;
+
+            // A build that is still in progress.
+            let ad = test_ad("pending");
+            let id = app
+                .post("/create")
+                .body_bytes(forest_encoding::to_vec(&ad)?)
+                .recv_string()
+                .await?;
+            let mh = multihash::Code::Blake2b256.digest(b"pending");
+            app.post(format!("/adv/{}/entryChunk", id))
+                .body_bytes(forest_encoding::to_vec(&vec![Ipld::Bytes(mh.to_bytes())])?)
+                .send()
+                .await?;
+
+            let stray = app
+                .state()
+                .blockstore
+                .read()
+                .await
+                .put(&Ipld::String("stray".into()), forest_cid::Code::Blake2b256)?;
+
+            let report: gc::GcReport = app.post("/gc").recv_json().await?;
+            assert_eq!(report.swept, 1);
+            // The head, its entry chunk and the pending chunk.
+            assert_eq!(report.live, 3);
+            let resp = app.get(format!("/{}", stray)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::NotFound);
+            let resp = app.get(format!("/{}", head)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            // The pending build can still be published, with its entries intact.
+            let cid: Cid = app
+                .post(format!("/adv/{}/publish", id))
+                .recv_string()
+                .await?
+                .parse()?;
+            let bs = app.state().blockstore.read().await;
+            let (_, ad) = chain::ads(&*bs, Some(cid)).next().unwrap()?;
+            assert_eq!(chain::entry_chunks(&*bs, cid, &ad)?.len(), 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_prune_removed_entries() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let provider = test_provider();
+            let app = test_app(provider.clone());
+            let removed = publish_test_ad(&app, "removed").await?;
+            let kept = publish_test_ad(&app, "kept").await?;
+            let rm = Advertisement {
+                IsRm: true,
+                ..test_ad("removed")
+            };
+            let id = app
+                .post("/create")
+                .body_bytes(forest_encoding::to_vec(&rm)?)
+                .recv_string()
+                .await?;
+            app.post(format!("/adv/{}/publish", id)).send().await?;
+
+            let entries_of = |cid: Cid| {
+                let provider = provider.clone();
+                async move {
+                    let bs = provider.blockstore.read().await;
+                    let (_, ad) = chain::ads(&*bs, Some(cid)).next().unwrap().unwrap();
+                    chain::link(&ad.Entries, cid).unwrap().unwrap()
+                }
+            };
+            let removed_entries = entries_of(removed).await;
+            let kept_entries = entries_of(kept).await;
+
+            // Still within the grace period.
+            let report = prune_removed(&provider, Duration::from_secs(3600)).await?;
+            assert_eq!(report.chunks, 0);
+
+            let report = prune_removed(&provider, Duration::from_secs(0)).await?;
+            assert_eq!(report.chunks, 1);
+            let mut resp = app.get(format!("/{}", removed_entries)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::NotFound);
+            assert_eq!(resp.body_string().await?, ENTRIES_UNAVAILABLE);
+            // The ad itself stays in the chain, as do the entries of other contexts.
+            let resp = app.get(format!("/{}", removed)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+            let resp = app.get(format!("/{}", kept_entries)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            let report: verify::FsckReport = app.get("/fsck").recv_json().await?;
+            assert!(report.errors.is_empty(), "{:?}", report.errors);
+            let report: gc::GcReport = app.post("/gc").recv_json().await?;
+            assert_eq!(report.swept, 0);
+
+            // An export without the pruned entries can still be imported.
+            let car_bytes = app.get("/export").recv_bytes().await?;
+            let other = test_app(test_provider());
+            let resp = other.post("/import").body_bytes(car_bytes).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+            let resp = other.get(format!("/{}", removed_entries)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::NotFound);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_compact() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let provider = test_provider();
+            let app = test_app(provider.clone());
+            let removed = publish_test_ad(&app, "removed").await?;
+            publish_test_ad(&app, "first").await?;
+            let rm = Advertisement {
+                IsRm: true,
+                ..test_ad("removed")
+            };
+            let id = app
+                .post("/create")
+                .body_bytes(forest_encoding::to_vec(&rm)?)
+                .recv_string()
+                .await?;
+            app.post(format!("/adv/{}/publish", id)).send().await?;
+            publish_test_ad(&app, "second").await?;
+
+            assert_eq!(provider.removals.read().await.len(), 1);
+
+            let report: compact::CompactReport = app.post("/compact?gc=true").recv_json().await?;
+            assert_eq!(report.ads_before, 4);
+            assert_eq!(report.ads_after, 3);
+            assert!(report.gc.unwrap().swept > 0);
+            assert!(provider.removals.read().await.is_empty());
+
+            let head: Cid = report.head.unwrap().parse()?;
+            assert_eq!(*provider.head.read().await, Some(head));
+            let bs = provider.blockstore.read().await;
+            let mut contexts = vec![];
+            for res in chain::ads(&*bs, Some(head)) {
+                let (cid, ad) = res?;
+                ad.verify_sig()?;
+                let chunks = if ad.IsRm { 0 } else { 1 };
+                assert_eq!(chain::entry_chunks(&*bs, cid, &ad)?.len(), chunks);
+                contexts.push((ad.ContextID, ad.IsRm));
+            }
+            // The removal stays, for indexers that ingested the removed ad but not the removal.
+            assert_eq!(
+                contexts,
+                vec![
+                    (Ipld::Bytes("second".into()), false),
+                    (Ipld::Bytes("removed".into()), true),
+                    (Ipld::Bytes("first".into()), false),
+                ]
+            );
+            assert!(bs.get_bytes(&removed)?.is_none());
+            drop(bs);
+
+            // A chain of nothing but a key handover would be left empty, so it is kept.
+            let provider = test_provider();
+            let app = test_app(provider.clone());
+            let handover = Advertisement {
+                ContextID: Ipld::Bytes(keys::ROTATION_CONTEXT_ID.to_vec()),
+                ..test_ad("")
+            };
+            let handover = put_test_ad(&*provider.blockstore.read().await, None, handover, &[]);
+            *provider.head.write().await = Some(handover);
+            let resp = app.post("/compact").send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Conflict);
+            assert_eq!(*provider.head.read().await, Some(handover));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_rotate_key() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let provider = test_provider();
+            let app = test_app(provider.clone());
+            let before = publish_test_ad(&app, "before").await?;
+            let old_id = provider.keys.read().await.peer_id();
+
+            let rotation: keys::Rotation = app.post("/keys/rotate").recv_json().await?;
+            assert_eq!(rotation.from, old_id.to_base58());
+            let new_id = provider.keys.read().await.peer_id();
+            assert_eq!(rotation.to, new_id.to_base58());
+
+            // The announcement follows the last ad, and is signed by the old key.
+            let announcement: Cid = rotation.announcement.unwrap().parse()?;
+            assert_eq!(*provider.head.read().await, Some(announcement));
+            {
+                let bs = provider.blockstore.read().await;
+                let (_, ad) = chain::ads(&*bs, Some(announcement)).next().unwrap()?;
+                ad.verify_sig()?;
+                assert_eq!(chain::link(&ad.PreviousID, announcement)?, Some(before));
+                assert_eq!(ad.Provider, old_id.to_base58());
+                let new_key = keys::verify_rotation(&ad, &old_id)?;
+                assert_eq!(libp2p::PeerId::from_public_key(&new_key), new_id);
+            }
+
+            // The head is now signed by the new key.
+            let signed_head: SignedHead = app.get("/head").recv_json().await?;
+            let (pk, head) = signed_head.open()?;
+            assert_eq!(head, announcement);
+            assert_eq!(libp2p::PeerId::from_public_key(&pk), new_id);
+
+            let info: serde_json::Value = app.get("/keys").recv_json().await?;
+            assert_eq!(info["current"], new_id.to_base58());
+            assert_eq!(info["retired"][0], old_id.to_base58());
+            assert_eq!(info["history"].as_array().unwrap().len(), 1);
+
+            // Ads for the old peer id are signed by the new key, not the retired one.
+            let ad = Advertisement {
+                Provider: old_id.to_base58(),
+                ..test_ad("after")
+            };
+            let id = app
+                .post("/create")
+                .body_bytes(forest_encoding::to_vec(&ad)?)
+                .recv_string()
+                .await?;
+            let cid: Cid = app
+                .post(format!("/adv/{}/publish", id))
+                .recv_string()
+                .await?
+                .parse()?;
+            let stored: Advertisement = provider.blockstore.read().await.get(&cid)?.unwrap();
+            let signature = stored.Signature.clone();
+            let expected = AdvertisementBuilder {
+                ad: Advertisement {
+                    Signature: Ipld::Bytes(vec![]),
+                    ..stored
+                },
+                entries_link: None,
+                entry_count: 0,
+            }
+            .build(&*provider.keys.read().await.signer())
+            .await?;
+            assert_eq!(signature, expected.Signature);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_provider_keys() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let publisher = Keypair::generate_ed25519();
+            let publisher_id = libp2p::PeerId::from_public_key(&publisher.public());
+            let provider_key = Keypair::generate_ed25519();
+            let mut provider_keys = keys::ProviderKeys::new(true);
+            let provider_id = provider_keys.insert(Arc::new(provider_key.clone()));
+            let provider = Provider::new(
+                IndexedStore::new(MemoryDB::default()),
+                keys::KeyRing::new(publisher.clone()),
+            )
+            .with_provider_keys(provider_keys);
+            let app = test_app(provider.clone());
+
+            let mut ad = Advertisement {
+                PreviousID: None,
+                Provider: provider_id.clone(),
+                Addresses: vec!["/ip4/127.0.0.1/tcp/9999".into()],
+                Signature: Ipld::Bytes(vec![]),
+                Entries: None,
+                Metadata: Ipld::Bytes(vec![]),
+                ContextID: Ipld::Bytes(b"ctx".to_vec()),
+                IsRm: true,
+            };
+            let id = app
+                .post("/create")
+                .body_bytes(forest_encoding::to_vec(&ad)?)
+                .recv_string()
+                .await?;
+            let cid = app
+                .post(format!("/adv/{}/publish", id))
+                .recv_string()
+                .await?;
+            let cid = Cid::from_str(&cid)?;
+
+            // Ed25519 signatures are deterministic, so signing the same ad with the provider key
+            // gives the stored signature.
+            let stored: Advertisement = provider.blockstore.read().await.get(&cid)?.unwrap();
+            stored.verify_sig()?;
+            let signature = stored.Signature.clone();
+            let expected = AdvertisementBuilder {
+                ad: Advertisement {
+                    Signature: Ipld::Bytes(vec![]),
+                    ..stored
+                },
+                entries_link: None,
+                entry_count: 0,
+            }
+            .build(&provider_key)
+            .await?;
+            assert_eq!(signature, expected.Signature);
+
+            // The head is signed by the publisher.
+            let signed_head: SignedHead = app.get("/head").recv_json().await?;
+            let (pk, head) = signed_head.open()?;
+            assert_eq!(head, cid);
+            assert_eq!(libp2p::PeerId::from_public_key(&pk), publisher_id);
+
+            // Providers without a key are refused.
+            ad.Provider = libp2p::PeerId::random().to_base58();
+            let resp = app
+                .post("/create")
+                .body_bytes(forest_encoding::to_vec(&ad)?)
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::BadRequest);
+
+            let info: serde_json::Value = app.get("/keys").recv_json().await?;
+            assert_eq!(info["providers"][0], provider_id);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_tenants() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let db = Arc::new(MemoryDB::default());
+            let mut app = test_app(test_provider());
+            let mut admin_app = test_app(test_provider());
+            let mut tenant_ids = vec![];
+            for _ in 0..2 {
+                let keys = keys::KeyRing::new(Keypair::generate_ed25519());
+                let id = keys.peer_id().to_base58();
+                let store = IndexedStore::new(Namespaced::new(db.clone(), format!("/p/{}/", id)));
+                let tenant =
+                    Provider::new(store, keys).with_provider_keys(keys::ProviderKeys::new(true));
+                mount_tenant(
+                    &mut app,
+                    &mut admin_app,
+                    tenant,
+                    std::slice::from_ref(&id),
+                    "",
+                    &BodyLimits::default(),
+                );
+                tenant_ids.push(id);
+            }
+            let (first, second) = (&tenant_ids[0], &tenant_ids[1]);
+
+            let ad = Advertisement {
+                PreviousID: None,
+                Provider: first.clone(),
+                Addresses: vec!["/ip4/127.0.0.1/tcp/9999".into()],
+                Signature: Ipld::Bytes(vec![]),
+                Entries: None,
+                Metadata: Ipld::Bytes(vec![]),
+                ContextID: Ipld::Bytes(b"ctx".to_vec()),
+                IsRm: true,
+            };
+            // Each tenant only signs its own ads.
+            let resp = admin_app
+                .post(format!("/p/{}/create", second))
+                .body_bytes(forest_encoding::to_vec(&ad)?)
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::BadRequest);
+
+            let id = admin_app
+                .post(format!("/p/{}/create", first))
+                .body_bytes(forest_encoding::to_vec(&ad)?)
+                .recv_string()
+                .await?;
+            let cid = admin_app
+                .post(format!("/p/{}/adv/{}/publish", first, id))
+                .recv_string()
+                .await?;
+
+            let signed_head: SignedHead = app.get(format!("/p/{}/head", first)).recv_json().await?;
+            let (pk, head) = signed_head.open()?;
+            assert_eq!(head.to_string(), cid);
+            assert_eq!(libp2p::PeerId::from_public_key(&pk).to_base58(), *first);
+            let resp = app.get(format!("/p/{}/{}", first, cid)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            // The other chains are untouched.
+            for path in [format!("/p/{}/head", second), "/head".to_string()] {
+                let resp = app.get(path).send().await?;
+                assert_eq!(resp.status(), tide::StatusCode::NotFound);
+            }
+            let resp = app.get(format!("/p/{}/{}", second, cid)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::NotFound);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_corrupt_block_not_served() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let provider = test_provider();
+            let app = test_app(provider.clone());
+            let cid = publish_test_ad(&app, "corrupt").await?;
+            let report: verify::FsckReport = app.get("/fsck").recv_json().await?;
+            assert!(report.errors.is_empty());
+
+            let bad_cid = publish_test_ad(&app, "never served").await?;
+            let good_bytes = provider.blockstore.read().await.get_bytes(&cid)?.unwrap();
+            provider
+                .blockstore
+                .write()
+                .await
+                .write(bad_cid.to_bytes(), good_bytes)?;
+
+            let resp = app.get(format!("/{}", bad_cid)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::InternalServerError);
+            assert_eq!(
+                app.get(format!("/{}", cid)).send().await?.status(),
+                tide::StatusCode::Ok
+            );
+
+            let report: verify::FsckReport = app.get("/fsck").recv_json().await?;
+            assert_eq!(report.errors.len(), 1);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_verify_on_serve() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let provider = test_provider().with_verify_ads(true);
+            let app = test_app(provider.clone());
+            let cid = publish_test_ad(&app, "signed").await?;
+
+            // A well formed ad whose signature no longer covers its fields.
+            let forged = {
+                let bs = provider.blockstore.read().await;
+                let mut ad: Advertisement = bs.get(&cid)?.unwrap();
+                ad.Addresses = vec!["/ip4/10.0.0.1/tcp/9999".into()];
+                bs.put(&ad, forest_cid::Code::Blake2b256)?
+            };
+            let resp = app.get(format!("/{}", forged)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::InternalServerError);
+            assert_eq!(
+                app.get(format!("/{}", cid)).send().await?.status(),
+                tide::StatusCode::Ok
+            );
+            // Without verify_on_serve only the cid is checked.
+            let unchecked = test_app(provider.clone().with_verify_ads(false));
+            let resp = unchecked.get(format!("/{}", forged)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_extended_head() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider().with_extended_head(true));
+            publish_test_ad(&app, "first").await?;
+            let first_head: SignedHead = app.get("/head").recv_json().await?;
+            let second = publish_test_ad(&app, "second").await?;
+            let signed_head: SignedHead = app.get("/head").recv_json().await?;
+            let check = signed_head::HeadCheck {
+                max_age: Some(Duration::from_secs(60)),
+                require_extended: true,
+                ..Default::default()
+            };
+            let (_, head, freshness) = signed_head.open_checked(&check)?;
+            assert_eq!(head, second);
+            let seq = freshness.unwrap().seq;
+
+            // The earlier head is still validly signed, but older than the one already seen.
+            let check = signed_head::HeadCheck {
+                last_seq: Some(seq),
+                ..check
+            };
+            assert!(matches!(
+                first_head.open_checked(&check),
+                Err(signed_head::SignedHeadError::Replayed { .. })
+            ));
+
+            // A restarted provider doesn't go back to an earlier seq.
+            let restarted = test_app(test_provider().with_extended_head(true));
+            publish_test_ad(&restarted, "restarted").await?;
+            let signed_head: SignedHead = restarted.get("/head").recv_json().await?;
+            assert!(signed_head.open_checked(&check).is_ok());
+
+            // Legacy heads are still served by default.
+            let app = test_app(test_provider());
+            publish_test_ad(&app, "legacy").await?;
+            let body = app.get("/head").recv_string().await?;
+            assert!(!body.contains("seq"));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_json_bodies() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider());
+            let id = app
+                .post("/create")
+                .body_json(&serde_json::json!({
+                    "Provider": "12D3KooWHHzSeKaY8xuZVzkLbKFfvNgPPeKhFBGrMbNzbm5akpqu",
+                    "Addresses": ["/ip4/127.0.0.1/tcp/9999"],
+                    "ContextID": "json-context",
+                    "Metadata": [{"protocol": "bitswap"}],
+                }))?
+                .recv_string()
+                .await?;
+
+            let mh = multihash::Code::Blake2b256.digest(b"json");
+            let resp = app
+                .post(format!("/adv/{}/entryChunk", id))
+                .body_json(&[bs58::encode(mh.to_bytes()).into_string()])?
+                .send()
+                .await?;
+            assert_eq!(resp.status(), StatusCode::Ok);
+            let resp = app
+                .post(format!("/adv/{}/entryChunk", id))
+                .body_json(&["not-a-multihash"])?
+                .send()
+                .await?;
+            assert_eq!(resp.status(), StatusCode::BadRequest);
+
+            let cid = app
+                .post(format!("/adv/{}/publish", id))
+                .recv_string()
+                .await?;
+            let bytes = app.get(format!("/{}", cid)).recv_bytes().await?;
+            let ad: Advertisement = from_slice(&bytes)?;
+            assert_eq!(ad.ContextID, Ipld::Bytes(b"json-context".to_vec()));
+            assert_eq!(ad.Metadata, Ipld::Bytes(vec![0x80, 0x12]));
+            ad.verify_sig()?;
+
+            let resp = app
+                .post("/create")
+                .body_json(&serde_json::json!({"ContextID": "no-provider"}))?
+                .send()
+                .await?;
+            assert_eq!(resp.status(), StatusCode::BadRequest);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_caching_headers() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider());
+            let cid = publish_test_ad(&app, "cached").await?;
+
+            let resp = app.get(format!("/{}", cid)).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+            assert_eq!(
+                resp.header(CACHE_CONTROL).unwrap().as_str(),
+                BLOCK_CACHE_CONTROL
+            );
+            let block_etag = resp.header(ETAG).unwrap().as_str().to_string();
+            assert_eq!(block_etag, format!("\"{}\"", cid));
+
+            let resp = app
+                .get(format!("/{}", cid))
+                .header(IF_NONE_MATCH, format!("\"other\", {}", block_etag))
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::NotModified);
+
+            let resp = app.get("/head").send().await?;
+            assert_eq!(
+                resp.header(CACHE_CONTROL).unwrap().as_str(),
+                HEAD_CACHE_CONTROL
+            );
+            let head_etag = resp.header(ETAG).unwrap().as_str().to_string();
+            let resp = app
+                .get("/head")
+                .header(IF_NONE_MATCH, head_etag.as_str())
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::NotModified);
+
+            // Publishing moves the head, so the old etag no longer matches.
+            publish_test_ad(&app, "newer").await?;
+            let resp = app
+                .get("/head")
+                .header(IF_NONE_MATCH, head_etag.as_str())
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_metrics() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider());
+            let cid = publish_test_ad(&app, "metrics").await?;
+            app.get("/head").send().await?;
+            app.get(format!("/{}", cid)).send().await?;
+            let missing = forest_cid::new_from_cbor(b"missing", forest_cid::Code::Blake2b256);
+            app.get(format!("/{}", missing)).send().await?;
+
+            let mut resp = app.get("/metrics").send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+            let body = resp.body_string().await?;
+            assert!(body.contains("provider_ads_created_total 1\n"));
+            assert!(body.contains("provider_entry_chunks_added_total 1\n"));
+            assert!(body.contains("provider_ads_published_total 1\n"));
+            assert!(body.contains("provider_entries_per_ad_sum 1\n"));
+            assert!(body.contains("provider_publish_latency_seconds_count 1\n"));
+            assert!(body.contains("provider_head_requests_total 1\n"));
+            assert!(body.contains("provider_block_fetches_total 2\n"));
+            assert!(body.contains("provider_block_not_found_total 1\n"));
+            assert!(body.contains("provider_pending_builders 0\n"));
+            // One ad and one entry chunk.
+            assert!(body.contains("provider_blockstore_blocks 2\n"));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_request_id() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let mut app = test_app(test_provider());
+            app.with(logging::request_log);
+
+            let resp = app.get("/head").send().await?;
+            let generated = resp.header(logging::REQUEST_ID_HEADER).unwrap().as_str();
+            assert_eq!(generated.len(), 16);
+
+            let resp = app
+                .get("/head")
+                .header(logging::REQUEST_ID_HEADER, "from-client")
+                .send()
+                .await?;
+            assert_eq!(
+                resp.header(logging::REQUEST_ID_HEADER).unwrap().as_str(),
+                "from-client"
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_admin_auth() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let mut app = tide::with_state(test_provider());
+            app.with(auth::Auth::new(serde_json::from_str(
+                r#"[
+                    {"token": "admin"},
+                    {"token": "reader", "scopes": ["read"]},
+                    {"token": "creator", "scopes": ["create"]}
+                ]"#,
+            )?));
+            app.at("/create").post(create);
+            app.at("/adv/:id/publish").post(publish_ad);
+            app.at("/metrics").get(metrics);
+
+            let ad = |is_rm| Advertisement {
+                IsRm: is_rm,
+                ..test_ad("auth")
+            };
+            let ad_bytes = forest_encoding::to_vec(&ad(false))?;
+            let rm_bytes = forest_encoding::to_vec(&ad(true))?;
+
+            let resp = app.get("/metrics").send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::Unauthorized);
+            let resp = app
+                .get("/metrics")
+                .header("Authorization", "Bearer wrong")
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Unauthorized);
+            let resp = app
+                .get("/metrics")
+                .header("Authorization", "Bearer reader")
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            let resp = app
+                .post("/create")
+                .header("Authorization", "Bearer reader")
+                .body_bytes(&ad_bytes)
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Forbidden);
+            // Removals need their own scope.
+            let resp = app
+                .post("/create")
+                .header("Authorization", "Bearer creator")
+                .body_bytes(&rm_bytes)
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Forbidden);
+
+            let id = app
+                .post("/create")
+                .header("Authorization", "Bearer creator")
+                .body_bytes(&ad_bytes)
+                .recv_string()
+                .await?;
+            let resp = app
+                .post(format!("/adv/{}/publish", id))
+                .header("Authorization", "Bearer creator")
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Forbidden);
+            let resp = app
+                .post(format!("/adv/{}/publish", id))
+                .header("Authorization", "Bearer admin")
+                .send()
+                .await?;
+            assert_eq!(resp.status(), tide::StatusCode::Ok);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_body_limit() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let mut app = tide::with_state(test_provider());
+            app.with(auth::Auth::new(vec![]));
+            mount_admin(
+                &mut app,
+                &BodyLimits {
+                    create: 16,
+                    ..BodyLimits::default()
+                },
+            );
+
+            let resp = app.post("/create").body_bytes(vec![0u8; 17]).send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::PayloadTooLarge);
+            // Other routes keep their own limit.
+            let resp = app
+                .post("/adv/1/entryChunk")
+                .body_bytes(vec![0u8; 17])
+                .send()
+                .await?;
+            assert_ne!(resp.status(), tide::StatusCode::PayloadTooLarge);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_rate_limit() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider());
+            publish_test_ad(&app, "rate").await?;
+
+            let mut public = tide::with_state(app.state().clone());
+            public.with(limits::RateLimit::new(limits::RateLimitConfig {
+                requests_per_second: 0.01,
+                burst: 2,
+                trust_forwarded_headers: false,
+            }));
+            mount_public(&mut public, "");
+            for _ in 0..2 {
+                let resp = public.get("/head").send().await?;
+                assert_eq!(resp.status(), tide::StatusCode::Ok);
+            }
+            let resp = public.get("/head").send().await?;
+            assert_eq!(resp.status(), tide::StatusCode::TooManyRequests);
+            assert_eq!(resp.header("Retry-After").unwrap().as_str(), "100");
+            Ok(())
+        })
+    }
+}
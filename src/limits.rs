@@ -0,0 +1,312 @@
+//! Request body size limits for the admin app, and per-client rate limiting for the public app.
+use async_std::io::ReadExt;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tide::http::headers::{CONTENT_LENGTH, FORWARDED, RETRY_AFTER};
+use tide::{Body, Middleware, Next, Request, StatusCode};
+
+/// Maximum request body size of each admin route, in bytes.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyLimits {
+    /// `/create`, a single advertisement without entries.
+    pub create: u64,
+    /// `/adv/:id/entryChunk`, a list of multihashes.
+    pub entry_chunk: u64,
+    /// `/adv/:id/publish`, which takes no body.
+    pub publish: u64,
+    /// `/import`, a CAR of a whole chain.
+    pub import: u64,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        BodyLimits {
+            create: 64 * 1024,
+            entry_chunk: 16 * 1024 * 1024,
+            publish: 1024,
+            import: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+fn too_large(limit: u64) -> tide::Result {
+    Err(tide::Error::from_str(
+        StatusCode::PayloadTooLarge,
+        format!("Request body is larger than {} bytes", limit),
+    ))
+}
+
+/// Rejects requests with a body over the limit with 413. Bodies without a `Content-Length` are
+/// read up to the limit and buffered.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyLimit(pub u64);
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for BodyLimit {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let limit = self.0;
+        let content_length = req
+            .header(CONTENT_LENGTH)
+            .and_then(|v| v.as_str().parse::<u64>().ok());
+        match content_length {
+            Some(len) if len > limit => return too_large(limit),
+            Some(_) => {}
+            None => {
+                let mut buf = vec![];
+                req.take_body()
+                    .take(limit + 1)
+                    .read_to_end(&mut buf)
+                    .await?;
+                if buf.len() as u64 > limit {
+                    return too_large(limit);
+                }
+                req.set_body(Body::from_bytes(buf));
+            }
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second allowed from each client IP.
+    pub requests_per_second: f64,
+    /// Requests a client can make at once before being limited.
+    pub burst: u32,
+    /// Identify clients by the last hop of `Forwarded`/`X-Forwarded-For` rather than the
+    /// connecting address. Only enable this behind a reverse proxy that appends to those headers.
+    #[serde(default)]
+    pub trust_forwarded_headers: bool,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Only sweep idle buckets once this many clients are tracked.
+const SWEEP_THRESHOLD: usize = 10_000;
+/// Sweeping is linear in the number of clients, so it runs at most this often.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Buckets {
+    clients: HashMap<String, Bucket>,
+    swept: Instant,
+}
+
+/// Token bucket per client IP. Responds with 429 and `Retry-After` once a client's bucket is
+/// empty.
+#[derive(Clone)]
+pub struct RateLimit {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimit {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimit {
+            config,
+            buckets: Arc::new(Mutex::new(Buckets {
+                clients: HashMap::new(),
+                swept: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes a token from the client's bucket, or returns how long until one is available.
+    fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let rate = self.config.requests_per_second;
+        let burst = self.config.burst as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.clients.len() >= SWEEP_THRESHOLD
+            && now.saturating_duration_since(buckets.swept) >= SWEEP_INTERVAL
+        {
+            // A bucket that would have refilled is the same as no bucket.
+            buckets.clients.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst
+            });
+            buckets.swept = now;
+        }
+
+        let bucket = buckets.clients.entry(client.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    fn client<State>(&self, req: &Request<State>) -> String {
+        let forwarded = if self.config.trust_forwarded_headers {
+            forwarded_for(req)
+        } else {
+            None
+        };
+        let addr = forwarded
+            .as_deref()
+            .or(req.peer_addr())
+            .unwrap_or("unknown");
+        // Limit by IP, not by connection.
+        match addr.parse::<SocketAddr>() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => match addr
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+            {
+                Ok(ip) => ip.to_string(),
+                Err(_) => addr.to_string(),
+            },
+        }
+    }
+}
+
+/// The client address added by the proxy in front of us: the last hop of `Forwarded`, or else of
+/// `X-Forwarded-For`. Earlier hops are whatever the client sent, so they can't be trusted.
+fn forwarded_for<State>(req: &Request<State>) -> Option<String> {
+    if let Some(values) = req.header(FORWARDED) {
+        let last = values.iter().flat_map(|v| v.as_str().split(',')).last()?;
+        return last.split(';').find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            name.eq_ignore_ascii_case("for")
+                .then(|| value.trim_matches('"').to_string())
+        });
+    }
+    let values = req.header(X_FORWARDED_FOR)?;
+    let last = values.iter().flat_map(|v| v.as_str().split(',')).last()?;
+    Some(last.trim().to_string())
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RateLimit {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        match self.check(&self.client(&req), Instant::now()) {
+            Ok(()) => Ok(next.run(req).await),
+            Err(wait) => {
+                let mut res = tide::Response::new(StatusCode::TooManyRequests);
+                res.insert_header(
+                    RETRY_AFTER,
+                    (wait.as_secs_f64().ceil() as u64).max(1).to_string(),
+                );
+                res.set_error(tide::Error::from_str(
+                    StatusCode::TooManyRequests,
+                    "Rate limit exceeded",
+                ));
+                Ok(res)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = RateLimit::new(RateLimitConfig {
+            requests_per_second: 2.0,
+            burst: 3,
+            trust_forwarded_headers: false,
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limit.check("10.0.0.1", start).is_ok());
+        }
+        let wait = limit.check("10.0.0.1", start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // Other clients have their own bucket.
+        assert!(limit.check("10.0.0.2", start).is_ok());
+
+        assert!(limit
+            .check("10.0.0.1", start + Duration::from_millis(500))
+            .is_ok());
+        assert!(limit
+            .check("10.0.0.1", start + Duration::from_millis(500))
+            .is_err());
+        // The bucket never holds more than the burst.
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limit.check("10.0.0.1", later).is_ok());
+        }
+        assert!(limit.check("10.0.0.1", later).is_err());
+    }
+
+    #[test]
+    fn test_sweep_interval() {
+        let limit = RateLimit::new(RateLimitConfig {
+            requests_per_second: 2.0,
+            burst: 3,
+            trust_forwarded_headers: false,
+        });
+        let start = Instant::now();
+        for i in 0..SWEEP_THRESHOLD {
+            limit.check(&format!("client-{}", i), start).unwrap();
+        }
+        // Every bucket has refilled, but the last sweep is too recent to run another.
+        limit.check("late", start + Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            limit.buckets.lock().unwrap().clients.len(),
+            SWEEP_THRESHOLD + 1
+        );
+        limit.check("later", start + SWEEP_INTERVAL).unwrap();
+        assert_eq!(limit.buckets.lock().unwrap().clients.len(), 1);
+    }
+
+    #[test]
+    fn test_forwarded_for() -> Result<(), Box<dyn std::error::Error>> {
+        use tide_testing::TideTestingExt;
+        async_std::task::block_on(async {
+            let mut app = tide::new();
+            app.with(RateLimit::new(RateLimitConfig {
+                requests_per_second: 0.01,
+                burst: 1,
+                trust_forwarded_headers: true,
+            }));
+            app.at("/").get(|_| async { Ok("ok") });
+
+            // The client picks the earlier hops, the proxy appends the last one.
+            let resp = app
+                .get("/")
+                .header("X-Forwarded-For", "1.1.1.1, 10.0.0.1")
+                .await?;
+            assert_eq!(resp.status(), StatusCode::Ok);
+            let resp = app
+                .get("/")
+                .header("X-Forwarded-For", "2.2.2.2, 10.0.0.1")
+                .await?;
+            assert_eq!(resp.status(), StatusCode::TooManyRequests);
+            let resp = app.get("/").header("X-Forwarded-For", "10.0.0.2").await?;
+            assert_eq!(resp.status(), StatusCode::Ok);
+
+            let resp = app
+                .get("/")
+                .header(
+                    "Forwarded",
+                    r#"for=1.1.1.1, for="[2001:db8::1]:4711";proto=https"#,
+                )
+                .await?;
+            assert_eq!(resp.status(), StatusCode::Ok);
+            let resp = app
+                .get("/")
+                .header("Forwarded", "for=3.3.3.3, for=[2001:db8::1]")
+                .await?;
+            assert_eq!(resp.status(), StatusCode::TooManyRequests);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,190 @@
+//! Logging through `tracing`, with text or JSON output, plus a middleware that runs every request
+//! in a span carrying its id, and writes an access log line when it is done.
+//!
+//! Configured with `LOG_FILTER` (falling back to `RUST_LOG`), e.g. `debug` or
+//! `info,tide=warn,http_index_provider_example=debug`, and `LOG_FORMAT=json` for ndjson output.
+//! Tide's own `log` records are forwarded into the same output.
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
+use tide::{Next, Request};
+use tracing::{error, info, info_span, warn, Instrument, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Tide logs every request at info on its own, our access log replaces that.
+const DEFAULT_FILTER: &str = "info,tide=warn";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// A subscriber writing the events `filter` lets through to `writer`. JSON lines carry the
+/// event's fields at the top level, and those of the spans it happened in under `spans`.
+fn subscriber<W>(
+    filter: &str,
+    format: Format,
+    writer: W,
+) -> Result<Box<dyn Subscriber + Send + Sync>, String>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_new(filter).map_err(|e| format!("Invalid LOG_FILTER: {}", e))?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(false)
+        .with_writer(writer);
+    Ok(match format {
+        Format::Text => Box::new(builder.finish()),
+        Format::Json => Box::new(builder.json().flatten_event(true).finish()),
+    })
+}
+
+/// Installs the subscriber, configured from the environment.
+pub fn init() -> Result<(), String> {
+    let filter = std::env::var("LOG_FILTER")
+        .or_else(|_| std::env::var("RUST_LOG"))
+        .unwrap_or(DEFAULT_FILTER.into());
+    let format = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => Format::Json,
+        Ok("text") | Err(_) => Format::Text,
+        Ok(other) => return Err(format!("Unknown LOG_FORMAT {}", other)),
+    };
+
+    subscriber(&filter, format, std::io::stdout)?
+        .try_init()
+        .map_err(|e| e.to_string())
+}
+
+/// Gives each request an id, taken from the `x-request-id` header or generated, and echoes it
+/// back. The request is handled in a span carrying the id, method and path, and its status and
+/// latency are logged once the response is ready.
+pub fn request_log<'a, State: Clone + Send + Sync + 'static>(
+    req: Request<State>,
+    next: Next<'a, State>,
+) -> Pin<Box<dyn Future<Output = tide::Result> + Send + 'a>> {
+    let id = req
+        .header(REQUEST_ID_HEADER)
+        .map(|v| v.as_str().to_string())
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = req.url().path(),
+    );
+    Box::pin(
+        async move {
+            let start = Instant::now();
+            let mut res = next.run(req).await;
+            res.insert_header(REQUEST_ID_HEADER, id.as_str());
+            let status = res.status() as u16;
+            let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+            match res.error() {
+                Some(err) if res.status().is_server_error() => {
+                    error!(status, latency_ms, error = %err, "Request failed");
+                }
+                Some(err) => warn!(status, latency_ms, error = %err, "Request rejected"),
+                None => info!(status, latency_ms, "Request served"),
+            }
+            Ok(res)
+        }
+        .instrument(span),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tide_testing::TideTestingExt;
+
+    /// Collects what a subscriber writes.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn lines(&self) -> Vec<serde_json::Value> {
+            let out = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            out.lines()
+                .map(|line| serde_json::from_str(line).expect("not json"))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        assert!(subscriber("info,tide=loud", Format::Text, std::io::sink).is_err());
+    }
+
+    #[test]
+    fn test_request_span() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = subscriber("info", Format::Json, move || writer.clone()).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            async_std::task::block_on(async {
+                let mut app = crate::tests::test_app(crate::tests::test_provider());
+                app.with(request_log);
+                let id: i64 = app
+                    .post("/create")
+                    .header(REQUEST_ID_HEADER, "create-1")
+                    .body_bytes(forest_encoding::to_vec(&crate::tests::test_ad("logged")).unwrap())
+                    .recv_string()
+                    .await
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let path = format!("/adv/{}/publish", id);
+                let cid = app
+                    .post(&path)
+                    .header(REQUEST_ID_HEADER, "publish-1")
+                    .recv_string()
+                    .await
+                    .unwrap();
+
+                let lines = captured.lines();
+                let line = |message: &str| {
+                    lines
+                        .iter()
+                        .find(|line| line["message"] == message)
+                        .unwrap_or_else(|| panic!("no {:?} in {:?}", message, lines))
+                };
+                // The handler's span scopes the temp ad id and cid for all of its events, inside
+                // the request's span.
+                let published = line("Published advertisement");
+                assert_eq!(published["span"]["temp_ad_id"], id);
+                assert_eq!(published["span"]["cid"], cid);
+                assert_eq!(published["spans"][0]["request_id"], "publish-1");
+                assert_eq!(published["spans"][0]["path"], path);
+
+                let created = line("Created temporary advertisement");
+                assert_eq!(created["spans"][0]["request_id"], "create-1");
+                assert_eq!(created["span"]["temp_ad_id"], id);
+
+                let served: Vec<_> = lines
+                    .iter()
+                    .filter(|line| line["message"] == "Request served")
+                    .collect();
+                assert_eq!(served.len(), 2);
+                assert_eq!(served[1]["span"]["request_id"], "publish-1");
+                assert_eq!(served[1]["status"], 200);
+            })
+        });
+    }
+}
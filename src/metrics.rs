@@ -0,0 +1,240 @@
+//! Counters and histograms exposed at `/metrics` in the Prometheus text format.
+//! Format: <https://prometheus.io/docs/instrumenting/exposition_formats/>
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+const ENTRIES_BUCKETS: &[f64] = &[0.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0];
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Histogram {
+    buckets: &'static [f64],
+    state: Mutex<HistogramState>,
+}
+
+struct HistogramState {
+    /// Observations per bucket, not cumulative. The last slot is the `+Inf` bucket.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Histogram {
+            buckets,
+            state: Mutex::new(HistogramState {
+                counts: vec![0; buckets.len() + 1],
+                sum: 0.0,
+            }),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let idx = self
+            .buckets
+            .iter()
+            .position(|le| value <= *le)
+            .unwrap_or(self.buckets.len());
+        let mut state = self.state.lock().unwrap();
+        state.counts[idx] += 1;
+        state.sum += value;
+    }
+}
+
+/// Values that are read from the provider's state when rendering, rather than counted.
+pub struct Gauges {
+    pub pending_builders: usize,
+    pub blockstore_blocks: usize,
+    pub blockstore_bytes: u64,
+}
+
+pub struct Metrics {
+    pub ads_created: Counter,
+    pub entry_chunks_added: Counter,
+    pub ads_published: Counter,
+    pub entries_per_ad: Histogram,
+    pub publish_latency_seconds: Histogram,
+    pub head_requests: Counter,
+    pub block_fetches: Counter,
+    pub block_not_found: Counter,
+    pub gc_blocks_swept: Counter,
+    pub entry_chunks_pruned: Counter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            ads_created: Counter::default(),
+            entry_chunks_added: Counter::default(),
+            ads_published: Counter::default(),
+            entries_per_ad: Histogram::new(ENTRIES_BUCKETS),
+            publish_latency_seconds: Histogram::new(LATENCY_BUCKETS),
+            head_requests: Counter::default(),
+            block_fetches: Counter::default(),
+            block_not_found: Counter::default(),
+            gc_blocks_swept: Counter::default(),
+            entry_chunks_pruned: Counter::default(),
+        }
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    write_header(out, name, "counter", help);
+    writeln!(out, "{} {}", name, counter.get()).unwrap();
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    write_header(out, name, "gauge", help);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+fn write_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    write_header(out, name, "histogram", help);
+    let state = histogram.state.lock().unwrap();
+    let mut cumulative = 0;
+    for (le, count) in histogram.buckets.iter().zip(&state.counts) {
+        cumulative += count;
+        writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative).unwrap();
+    }
+    cumulative += state.counts[histogram.buckets.len()];
+    writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative).unwrap();
+    writeln!(out, "{}_sum {}", name, state.sum).unwrap();
+    writeln!(out, "{}_count {}", name, cumulative).unwrap();
+}
+
+impl Metrics {
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+        write_counter(
+            &mut out,
+            "provider_ads_created_total",
+            "Advertisements started with /create.",
+            &self.ads_created,
+        );
+        write_counter(
+            &mut out,
+            "provider_entry_chunks_added_total",
+            "Entry chunks added to advertisements being built.",
+            &self.entry_chunks_added,
+        );
+        write_counter(
+            &mut out,
+            "provider_ads_published_total",
+            "Advertisements published to the chain.",
+            &self.ads_published,
+        );
+        write_histogram(
+            &mut out,
+            "provider_entries_per_ad",
+            "Number of multihashes in each published advertisement.",
+            &self.entries_per_ad,
+        );
+        write_histogram(
+            &mut out,
+            "provider_publish_latency_seconds",
+            "Time taken to sign and store an advertisement.",
+            &self.publish_latency_seconds,
+        );
+        write_counter(
+            &mut out,
+            "provider_head_requests_total",
+            "Requests for the signed head.",
+            &self.head_requests,
+        );
+        write_counter(
+            &mut out,
+            "provider_block_fetches_total",
+            "Requests for blocks by cid.",
+            &self.block_fetches,
+        );
+        write_counter(
+            &mut out,
+            "provider_block_not_found_total",
+            "Requests for blocks that are not in the blockstore.",
+            &self.block_not_found,
+        );
+        write_counter(
+            &mut out,
+            "provider_gc_blocks_swept_total",
+            "Unreachable blocks deleted by garbage collection.",
+            &self.gc_blocks_swept,
+        );
+        write_counter(
+            &mut out,
+            "provider_entry_chunks_pruned_total",
+            "Entry chunks of removed contexts deleted after the grace period.",
+            &self.entry_chunks_pruned,
+        );
+        write_gauge(
+            &mut out,
+            "provider_pending_builders",
+            "Advertisements created but not yet published.",
+            gauges.pending_builders as u64,
+        );
+        write_gauge(
+            &mut out,
+            "provider_blockstore_blocks",
+            "Number of blocks in the blockstore.",
+            gauges.blockstore_blocks as u64,
+        );
+        write_gauge(
+            &mut out,
+            "provider_blockstore_bytes",
+            "Total size of the blocks in the blockstore.",
+            gauges.blockstore_bytes,
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram() {
+        let metrics = Metrics::default();
+        metrics.entries_per_ad.observe(5.0);
+        metrics.entries_per_ad.observe(50.0);
+        metrics.entries_per_ad.observe(5_000_000.0);
+        metrics.ads_published.inc();
+
+        let out = metrics.render(&Gauges {
+            pending_builders: 2,
+            blockstore_blocks: 3,
+            blockstore_bytes: 4,
+        });
+        assert!(out.contains("provider_ads_published_total 1\n"));
+        assert!(out.contains("provider_entries_per_ad_bucket{le=\"0\"} 0\n"));
+        assert!(out.contains("provider_entries_per_ad_bucket{le=\"10\"} 1\n"));
+        assert!(out.contains("provider_entries_per_ad_bucket{le=\"100\"} 2\n"));
+        assert!(out.contains("provider_entries_per_ad_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("provider_entries_per_ad_count 3\n"));
+        assert!(out.contains("provider_pending_builders 2\n"));
+        assert!(out.contains("# TYPE provider_publish_latency_seconds histogram\n"));
+    }
+}
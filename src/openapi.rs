@@ -0,0 +1,662 @@
+//! OpenAPI description of the public and admin routes, served at `/openapi.json` on the admin port.
+//! The routes and response shapes are checked against the handlers by the tests below.
+use crate::car::CAR_CONTENT_TYPE;
+use crate::limits::BodyLimits;
+use crate::metrics::METRICS_CONTENT_TYPE;
+use crate::{AdminRoute, PublicRoute, DAG_CBOR_CONTENT_TYPE, PUBLIC_LAYOUTS};
+use serde_json::{json, Map, Value};
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain";
+
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn content(media_type: &str, schema: Value) -> Value {
+    json!({ media_type: { "schema": schema } })
+}
+
+fn body(description: String, content: Value) -> Value {
+    json!({ "description": description, "required": true, "content": content })
+}
+
+/// An operation whose success response is `ok`, which also fails with the listed statuses. Every
+/// admin route can also fail auth, and be refused during shutdown.
+fn operation(tag: &str, summary: &str, request: Option<Value>, ok: Value, errors: &[u16]) -> Value {
+    let mut responses = Map::new();
+    responses.insert("200".into(), ok);
+    let mut errors = errors.to_vec();
+    if tag == "admin" {
+        errors.extend([401, 403, 503]);
+    } else {
+        errors.push(429);
+    }
+    for status in errors {
+        responses.insert(
+            status.to_string(),
+            json!({ "$ref": format!("#/components/responses/{}", status) }),
+        );
+    }
+    let mut op = json!({ "tags": [tag], "summary": summary, "responses": responses });
+    if let Some(request) = request {
+        op["requestBody"] = request;
+    }
+    op
+}
+
+fn ok(description: &str, content: Value) -> Value {
+    json!({ "description": description, "content": content })
+}
+
+/// A route's path in the document's syntax, `{param}` rather than tide's `:param`.
+fn document_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) => format!("{{{}}}", param),
+            None => segment.into(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn public_path(route: PublicRoute) -> Value {
+    match route {
+        PublicRoute::Head => json!({ "get": operation(
+            "public",
+            "The signed cid of the latest advertisement",
+            None,
+            ok("The signed head", content(JSON, schema("SignedHead"))),
+            &[404],
+        )}),
+        PublicRoute::Block => json!({
+            "parameters": [{
+                "name": "cid", "in": "path", "required": true,
+                "schema": schema("Cid"),
+            }],
+            "get": operation(
+                "public",
+                "An advertisement or entry chunk",
+                None,
+                ok(
+                    "The block, an Advertisement or an EntryChunk",
+                    content(DAG_CBOR_CONTENT_TYPE, json!({
+                        "oneOf": [schema("Advertisement"), schema("EntryChunk")],
+                    })),
+                ),
+                &[404, 500],
+            ),
+        }),
+    }
+}
+
+fn id_parameter() -> Value {
+    json!([{
+        "name": "id", "in": "path", "required": true,
+        "schema": schema("TempId"),
+    }])
+}
+
+fn admin_path(route: AdminRoute, limits: &BodyLimits) -> Value {
+    let limit = |what: &str, bytes: u64| format!("{}, at most {} bytes", what, bytes);
+    match route {
+        AdminRoute::Create => json!({ "post": operation(
+            "admin",
+            "Start an advertisement",
+            Some(body(
+                limit("Every field except Entries, PreviousID and Signature", limits.create),
+                json!({
+                    DAG_CBOR_CONTENT_TYPE: { "schema": schema("Advertisement") },
+                    JSON: { "schema": schema("AdvertisementJson") },
+                }),
+            )),
+            ok("The temporary id of the advertisement", content(JSON, schema("TempId"))),
+            &[400, 413, 500],
+        )}),
+        AdminRoute::EntryChunk => json!({
+            "parameters": id_parameter(),
+            "post": operation(
+                "admin",
+                "Link another chunk of entries to the advertisement",
+                Some(body(
+                    limit("A list of multihashes", limits.entry_chunk),
+                    json!({
+                        DAG_CBOR_CONTENT_TYPE: { "schema": schema("Entries") },
+                        JSON: { "schema": schema("EntriesJson") },
+                    }),
+                )),
+                json!({ "description": "The entries were linked" }),
+                &[400, 404, 413, 500],
+            ),
+        }),
+        AdminRoute::Publish => json!({
+            "parameters": id_parameter(),
+            "post": operation(
+                "admin",
+                "Sign the advertisement and make it the head",
+                None,
+                ok("The cid of the advertisement", content(TEXT, schema("Cid"))),
+                &[400, 404, 500],
+            ),
+        }),
+        AdminRoute::Export => {
+            let mut export = operation(
+                "admin",
+                "Export the chain as a CAR file",
+                None,
+                ok(
+                    "The chain, rooted at the head",
+                    content(
+                        CAR_CONTENT_TYPE,
+                        json!({ "type": "string", "format": "binary" }),
+                    ),
+                ),
+                &[400, 404, 500],
+            );
+            export["parameters"] = json!([{
+                "name": "until", "in": "query", "required": false,
+                "description": "Stop at this advertisement instead of the start of the chain",
+                "schema": schema("Cid"),
+            }]);
+            json!({ "get": export })
+        }
+        AdminRoute::Import => {
+            let mut import = operation(
+                "admin",
+                "Import a chain exported by another provider and make its root the head. The CAR \
+                 must hold a complete chain, or one whose oldest advertisement follows the current \
+                 head",
+                Some(body(
+                    limit("A CAR file", limits.import),
+                    content(
+                        CAR_CONTENT_TYPE,
+                        json!({ "type": "string", "format": "binary" }),
+                    ),
+                )),
+                ok("The new head", content(TEXT, schema("Cid"))),
+                &[400, 409, 413, 500],
+            );
+            import["parameters"] = json!([{
+                "name": "replace", "in": "query", "required": false,
+                "description": "Replace the current chain with an unrelated one",
+                "schema": { "type": "boolean", "default": false },
+            }]);
+            json!({ "post": import })
+        }
+        AdminRoute::Fsck => json!({ "get": operation(
+            "admin",
+            "Verify every block reachable from the head, and every advertisement's signature",
+            None,
+            ok("The problems found", content(JSON, schema("FsckReport"))),
+            &[500],
+        )}),
+        AdminRoute::Gc => json!({ "post": operation(
+            "admin",
+            "Delete blocks no longer reachable from the head",
+            None,
+            ok("What was collected", content(JSON, schema("GcReport"))),
+            &[500],
+        )}),
+        AdminRoute::Compact => {
+            let mut compact = operation(
+                "admin",
+                "Replace the chain with one holding only the live advertisements",
+                None,
+                ok("The new chain", content(JSON, schema("CompactReport"))),
+                &[409, 500],
+            );
+            compact["parameters"] = json!([{
+                "name": "gc", "in": "query", "required": false,
+                "description": "Collect the old chain straight away",
+                "schema": { "type": "boolean" },
+            }]);
+            json!({ "post": compact })
+        }
+        AdminRoute::Keys => json!({ "get": operation(
+            "admin",
+            "List the publisher keys and rotations",
+            None,
+            ok("The keys", content(JSON, schema("KeysInfo"))),
+            &[],
+        )}),
+        AdminRoute::RotateKey => json!({ "post": operation(
+            "admin",
+            "Switch to a newly generated publisher key",
+            None,
+            ok("The rotation", content(JSON, schema("Rotation"))),
+            &[400, 500],
+        )}),
+        AdminRoute::Metrics => json!({ "get": operation(
+            "admin",
+            "Prometheus metrics",
+            None,
+            ok("The metrics", content(METRICS_CONTENT_TYPE, json!({ "type": "string" }))),
+            &[],
+        )}),
+        AdminRoute::OpenApi => json!({ "get": operation(
+            "admin",
+            "This document",
+            None,
+            ok("The OpenAPI document", content(JSON, json!({ "type": "object" }))),
+            &[],
+        )}),
+    }
+}
+
+fn schemas() -> Value {
+    let link = json!({
+        "type": "object",
+        "properties": { "/": schema("Cid") },
+        "required": ["/"],
+    });
+    json!({
+        "Cid": { "type": "string", "example": "bafy2bzaceaxm23epjsmh75yvzcecsrbavlmkcxnva66bkdebdcnyw3bjrc74u" },
+        "Link": link,
+        "Bytes": {
+            "type": "object",
+            "properties": {
+                "/": {
+                    "type": "object",
+                    "properties": { "bytes": { "type": "string", "format": "byte" } },
+                    "required": ["bytes"],
+                },
+            },
+            "required": ["/"],
+        },
+        "TempId": {
+            "type": "integer",
+            "format": "int64",
+            "description": "Identifies an advertisement that is still being built",
+        },
+        "Advertisement": {
+            "type": "object",
+            "description": "Shown in its dag-json form. On the wire it is the same structure \
+                encoded as dag-cbor, with links as cids and bytes as byte strings.",
+            "properties": {
+                "PreviousID": { "allOf": [schema("Link")], "nullable": true },
+                "Provider": { "type": "string", "description": "Peer id of the provider" },
+                "Addresses": { "type": "array", "items": { "type": "string" } },
+                "Signature": schema("Bytes"),
+                "Entries": { "allOf": [schema("Link")], "nullable": true },
+                "ContextID": schema("Bytes"),
+                "Metadata": schema("Bytes"),
+                "IsRm": { "type": "boolean" },
+            },
+            "required": ["Provider", "Addresses", "Signature", "ContextID", "Metadata", "IsRm"],
+        },
+        "Entries": {
+            "type": "array",
+            "description": "Multihashes, as dag-cbor byte strings",
+            "items": schema("Bytes"),
+        },
+        "AdvertisementJson": {
+            "type": "object",
+            "description": "The JSON form of an Advertisement",
+            "properties": {
+                "Provider": { "type": "string", "description": "Peer id of the provider" },
+                "Addresses": { "type": "array", "items": { "type": "string" } },
+                "ContextID": {
+                    "oneOf": [
+                        { "type": "string", "description": "Used as UTF-8 bytes" },
+                        {
+                            "type": "object",
+                            "properties": { "base64": { "type": "string", "format": "byte" } },
+                            "required": ["base64"],
+                        },
+                    ],
+                },
+                "Metadata": { "type": "array", "items": schema("Metadata") },
+                "IsRm": { "type": "boolean" },
+            },
+            "required": ["Provider", "ContextID"],
+            "additionalProperties": false,
+        },
+        "Metadata": {
+            "type": "object",
+            "description": "A retrieval protocol, encoded as its multicodec code and parameters. \
+                `raw` takes metadata that is already encoded.",
+            "properties": {
+                "protocol": { "type": "string", "enum": ["bitswap", "graphsync-filecoinv1", "raw"] },
+                "PieceCID": schema("Cid"),
+                "VerifiedDeal": { "type": "boolean" },
+                "FastRetrieval": { "type": "boolean" },
+                "base64": { "type": "string", "format": "byte" },
+            },
+            "required": ["protocol"],
+        },
+        "EntriesJson": {
+            "type": "array",
+            "description": "Multihashes, as base58 strings",
+            "items": { "type": "string", "example": "QmPZ9gcCEpqKTo6aq61g2nXGUhM4iCL3ewB6LDXZCtioEB" },
+        },
+        "EntryChunk": {
+            "type": "object",
+            "properties": {
+                "Entries": schema("Entries"),
+                "Next": schema("Link"),
+            },
+            "required": ["Entries"],
+        },
+        "SignedHead": {
+            "type": "object",
+            "properties": {
+                "head": schema("Link"),
+                "sig": schema("Bytes"),
+                "pubkey": schema("Bytes"),
+                "seq": { "type": "integer", "format": "int64" },
+                "timestamp": { "type": "integer", "format": "int64" },
+            },
+            "required": ["head", "sig", "pubkey"],
+        },
+        "FsckReport": {
+            "type": "object",
+            "properties": {
+                "checked": { "type": "integer" },
+                "errors": { "type": "array", "items": { "type": "string" } },
+                "first_bad_signature": {
+                    "allOf": [schema("Cid")],
+                    "nullable": true,
+                    "description": "The first advertisement, walking back from the head, whose \
+                        signature doesn't verify",
+                },
+            },
+            "required": ["checked", "errors"],
+        },
+        "GcReport": {
+            "type": "object",
+            "properties": {
+                "live": { "type": "integer" },
+                "swept": { "type": "integer" },
+                "bytes_freed": { "type": "integer" },
+            },
+            "required": ["live", "swept", "bytes_freed"],
+        },
+        "CompactReport": {
+            "type": "object",
+            "properties": {
+                "head": { "allOf": [schema("Cid")], "nullable": true },
+                "ads_before": { "type": "integer" },
+                "ads_after": { "type": "integer" },
+                "gc": { "allOf": [schema("GcReport")], "nullable": true },
+            },
+            "required": ["head", "ads_before", "ads_after", "gc"],
+        },
+        "Rotation": {
+            "type": "object",
+            "properties": {
+                "from": { "type": "string" },
+                "to": { "type": "string" },
+                "at": { "type": "integer" },
+                "announcement": { "allOf": [schema("Cid")], "nullable": true },
+            },
+            "required": ["from", "to", "at", "announcement"],
+        },
+        "KeysInfo": {
+            "type": "object",
+            "properties": {
+                "current": { "type": "string" },
+                "retired": { "type": "array", "items": { "type": "string" } },
+                "history": { "type": "array", "items": schema("Rotation") },
+                "providers": { "type": "array", "items": { "type": "string" } },
+            },
+            "required": ["current", "retired", "history", "providers"],
+        },
+        "Error": {
+            "type": "string",
+            "description": "Most errors have an empty body. Pruned entry chunks are reported \
+                as `Entries are no longer available`.",
+        },
+    })
+}
+
+fn responses() -> Value {
+    let error = |description: &str| json!({ "description": description, "content": content(TEXT, schema("Error")) });
+    json!({
+        "400": error("The request is invalid"),
+        "401": error("Missing or unknown admin token"),
+        "403": error("The token lacks the scope for this route"),
+        "404": error("Not found"),
+        "409": error("The request conflicts with the current chain"),
+        "413": error("The body is over the route's limit"),
+        "429": error("Over the rate limit, retry after the `Retry-After` header"),
+        "500": error("The request failed"),
+        "503": error("Shutting down"),
+    })
+}
+
+/// Builds the document, with the body limits in force.
+pub(crate) fn document(limits: &BodyLimits) -> Value {
+    let mut paths = Map::new();
+    for layout in PUBLIC_LAYOUTS {
+        for route in PublicRoute::ALL {
+            let path = format!("{}{}", layout, document_path(route.path()));
+            paths.insert(path, public_path(route));
+        }
+    }
+    for route in AdminRoute::ALL {
+        paths.insert(document_path(route.path()), admin_path(route, limits));
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "HTTP index provider",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Routes tagged `public` are served on the public port, under \
+                `path_prefix`, and those tagged `admin` on the admin port. Tenant chains serve \
+                the same routes under `/p/{peer id}` on both ports, as in the second server.",
+        },
+        "servers": [
+            { "url": "/", "description": "The default chain" },
+            {
+                "url": "/p/{peerId}",
+                "description": "A tenant's chain",
+                "variables": {
+                    "peerId": {
+                        "default": "<peer id>",
+                        "description": "The current peer id of the tenant's key, or one it has \
+                            retired",
+                    },
+                },
+            },
+        ],
+        "tags": [
+            { "name": "public", "description": "For indexers" },
+            { "name": "admin", "description": "For building and managing the chain" },
+        ],
+        "security": [{}, { "bearer": [] }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "responses": responses(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{publish_test_ad, test_ad, test_app, test_provider};
+    use multihash::MultihashDigest;
+    use std::collections::BTreeSet;
+    use tide_testing::TideTestingExt;
+
+    /// Follows a `$ref` within the document.
+    fn resolve<'a>(doc: &'a Value, value: &'a Value) -> &'a Value {
+        match value["$ref"].as_str() {
+            Some(path) => {
+                let target = doc.pointer(path.trim_start_matches('#')).expect(path);
+                resolve(doc, target)
+            }
+            None => value,
+        }
+    }
+
+    fn check_refs(doc: &Value, value: &Value) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(path)) = map.get("$ref") {
+                    assert!(
+                        doc.pointer(path.trim_start_matches('#')).is_some(),
+                        "dangling {}",
+                        path
+                    );
+                }
+                map.values().for_each(|v| check_refs(doc, v));
+            }
+            Value::Array(values) => values.iter().for_each(|v| check_refs(doc, v)),
+            _ => {}
+        }
+    }
+
+    /// Checks a JSON body against the parts of the schema the handlers can get wrong: the type,
+    /// and the required properties of objects.
+    fn check_json(doc: &Value, schema: &Value, body: &Value) {
+        let schema = resolve(doc, schema);
+        match schema["type"].as_str() {
+            Some("object") => {
+                for field in schema["required"].as_array().into_iter().flatten() {
+                    let field = field.as_str().unwrap();
+                    assert!(body.get(field).is_some(), "missing {} in {}", field, body);
+                }
+                if let Some(properties) = schema["properties"].as_object() {
+                    for (name, property) in properties {
+                        match body.get(name) {
+                            Some(Value::Null) | None => {}
+                            Some(value) => check_json(doc, property, value),
+                        }
+                    }
+                }
+            }
+            Some("integer") => {
+                assert!(body.is_i64() || body.is_u64(), "{} is not an integer", body)
+            }
+            Some("string") => assert!(body.is_string(), "{} is not a string", body),
+            Some("array") => body
+                .as_array()
+                .expect("not an array")
+                .iter()
+                .for_each(|item| check_json(doc, &schema["items"], item)),
+            Some("boolean") => assert!(body.is_boolean()),
+            _ => {
+                if let Some(all_of) = schema["allOf"].as_array() {
+                    all_of.iter().for_each(|s| check_json(doc, s, body));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_document_covers_routes() {
+        let doc = document(&BodyLimits::default());
+        check_refs(&doc, &doc);
+        // No two routes share a documented path.
+        let routes = PUBLIC_LAYOUTS.len() * PublicRoute::ALL.len() + AdminRoute::ALL.len();
+        assert_eq!(doc["paths"].as_object().unwrap().len(), routes);
+        assert_eq!(document_path("/adv/:id/publish"), "/adv/{id}/publish");
+        assert_eq!(doc["servers"][1]["url"], "/p/{peerId}");
+    }
+
+    #[test]
+    fn test_handlers_match_document() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let app = test_app(test_provider());
+            let doc: Value = app.get("/openapi.json").recv_json().await?;
+            assert_eq!(doc, document(&BodyLimits::default()));
+            let cid = publish_test_ad(&app, "openapi").await?;
+            let export = app.get("/export").recv_bytes().await?;
+
+            let ad = forest_encoding::to_vec(&test_ad("openapi"))?;
+            let id: i64 = app.post("/create").body_bytes(&ad).recv_json().await?;
+            let mh = multihash::Code::Sha2_256.digest(b"openapi");
+            let entries = forest_encoding::to_vec(&vec![forest_ipld::Ipld::Bytes(mh.to_bytes())])?;
+
+            // One request per documented operation, in an order where each succeeds.
+            let requests = vec![
+                ("get", "/head", "/head".into(), None),
+                ("get", "/{cid}", format!("/{}", cid), None),
+                ("get", "/ipni/v1/ad/head", "/ipni/v1/ad/head".into(), None),
+                (
+                    "get",
+                    "/ipni/v1/ad/{cid}",
+                    format!("/ipni/v1/ad/{}", cid),
+                    None,
+                ),
+                (
+                    "post",
+                    "/adv/{id}/entryChunk",
+                    format!("/adv/{}/entryChunk", id),
+                    Some(entries),
+                ),
+                (
+                    "post",
+                    "/adv/{id}/publish",
+                    format!("/adv/{}/publish", id),
+                    None,
+                ),
+                ("post", "/create", "/create".into(), Some(ad)),
+                ("get", "/export", "/export".into(), None),
+                (
+                    "post",
+                    "/import",
+                    "/import?replace=true".into(),
+                    Some(export),
+                ),
+                ("get", "/fsck", "/fsck".into(), None),
+                ("post", "/gc", "/gc".into(), None),
+                ("post", "/compact", "/compact".into(), None),
+                ("get", "/keys", "/keys".into(), None),
+                ("post", "/keys/rotate", "/keys/rotate".into(), None),
+                ("get", "/metrics", "/metrics".into(), None),
+                ("get", "/openapi.json", "/openapi.json".into(), None),
+            ];
+            let mut operations = BTreeSet::new();
+            for (path, item) in doc["paths"].as_object().unwrap() {
+                for method in ["get", "post"] {
+                    if item.get(method).is_some() {
+                        operations.insert((method, path.as_str()));
+                    }
+                }
+            }
+            let requested: BTreeSet<_> = requests.iter().map(|(m, p, _, _)| (*m, *p)).collect();
+            assert_eq!(operations, requested);
+
+            for (method, path, url, body) in requests {
+                let op = &doc["paths"][path][method];
+                let mut res = match method {
+                    "get" => app.get(&url).await?,
+                    _ => app.post(&url).body_bytes(body.unwrap_or_default()).await?,
+                };
+                let status = u16::from(res.status()).to_string();
+                assert!(
+                    op["responses"].get(&status).is_some(),
+                    "{} {} returned undocumented {}",
+                    method,
+                    path,
+                    status
+                );
+                let documented = &op["responses"][&status]["content"];
+                let content_type = res.content_type().map(|mime| mime.essence().to_string());
+                match documented.as_object() {
+                    None => {}
+                    Some(media_types) => {
+                        let content_type = content_type.expect("no content type");
+                        let (_, media_type) = media_types
+                            .iter()
+                            .find(|(name, _)| name.split(';').next() == Some(&content_type))
+                            .unwrap_or_else(|| {
+                                panic!("{} {} returned {}", method, path, content_type)
+                            });
+                        if content_type == JSON {
+                            let body: Value = res.body_json().await?;
+                            check_json(&doc, &media_type["schema"], &body);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,90 @@
+//! Just enough protobuf to read and write libp2p keys and signed envelopes, for the encodings the
+//! libp2p version we build on doesn't handle itself.
+
+/// Unsigned LEB128, which multiformats and CAR sections use as well.
+pub(crate) fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+pub(crate) fn put_uint(buf: &mut Vec<u8>, field: u8, n: u64) {
+    buf.push(field << 3);
+    put_varint(buf, n);
+}
+
+pub(crate) fn put_bytes(buf: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    // Length delimited wire type.
+    buf.push(field << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Value<'a> {
+    Uint(u64),
+    Bytes(&'a [u8]),
+}
+
+pub(crate) fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// Splits a message into its fields, in order. Only the varint and length delimited wire types
+/// are supported, which is all libp2p uses. `None` if the message is malformed.
+pub(crate) fn read_fields(mut bytes: &[u8]) -> Option<Vec<(u64, Value<'_>)>> {
+    let mut fields = vec![];
+    while !bytes.is_empty() {
+        let tag = read_varint(&mut bytes)?;
+        let value = match tag & 7 {
+            0 => Value::Uint(read_varint(&mut bytes)?),
+            2 => {
+                let len = read_varint(&mut bytes)? as usize;
+                if len > bytes.len() {
+                    return None;
+                }
+                let (value, rest) = bytes.split_at(len);
+                bytes = rest;
+                Value::Bytes(value)
+            }
+            _ => return None,
+        };
+        fields.push((tag >> 3, value));
+    }
+    Some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let long = vec![7; 300];
+        let mut buf = vec![];
+        put_uint(&mut buf, 1, 0);
+        put_bytes(&mut buf, 2, &long);
+        put_uint(&mut buf, 3, 1 << 40);
+        assert_eq!(
+            read_fields(&buf).unwrap(),
+            vec![
+                (1, Value::Uint(0)),
+                (2, Value::Bytes(&long)),
+                (3, Value::Uint(1 << 40))
+            ]
+        );
+        assert!(read_fields(&buf[..buf.len() - 1]).is_none());
+        assert!(read_fields(&[0x12, 0x05, 0x00]).is_none());
+    }
+}
@@ -0,0 +1,144 @@
+//! Pruning of the entry chunks of removed contexts. Once an `IsRm` ad has been published and
+//! indexers have had time to ingest it, they no longer need the entries of the earlier ads for
+//! that context. The ads themselves are kept, since they make up the chain.
+use crate::chain::{self, ChainError};
+use forest_cid::Cid;
+use ipld_blockstore::BlockStore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PruneConfig {
+    /// How long after a context's removal its entries are kept.
+    pub grace_period_secs: u64,
+    /// How often to look for entries to prune.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    3600
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct PruneReport {
+    /// Entry chunks deleted.
+    pub chunks: usize,
+    pub bytes_freed: u64,
+}
+
+/// Returns the entry chunks that are only used by ads for contexts that were later removed, where
+/// `expired` says whether a removal ad is past its grace period. Chunks also used by ads that are
+/// still live (including ads that re-add a context after its removal) are not returned.
+pub(crate) fn prunable<BS: BlockStore>(
+    bs: &BS,
+    head: Option<Cid>,
+    pruned: &HashSet<Cid>,
+    expired: impl Fn(&Cid) -> bool,
+) -> Result<HashSet<Cid>, ChainError> {
+    // Walking from the head, a provider's context is removed for every ad older than its removal.
+    let mut removed = HashSet::new();
+    let mut candidates = HashSet::new();
+    let mut keep = HashSet::new();
+    for res in chain::ads(bs, head) {
+        let (cid, ad) = res?;
+        let key = chain::removal_key(cid, &ad)?;
+        if ad.IsRm {
+            if expired(&cid) {
+                removed.insert(key);
+            }
+            continue;
+        }
+        let chunks = chain::retained_entry_chunks(bs, cid, &ad, pruned)?
+            .into_iter()
+            .map(|(chunk_cid, _)| chunk_cid);
+        if removed.contains(&key) {
+            candidates.extend(chunks);
+        } else {
+            keep.extend(chunks);
+        }
+    }
+    Ok(candidates.difference(&keep).copied().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advertisement::Advertisement;
+    use crate::tests::{put_test_ad, test_ad};
+    use forest_db::{MemoryDB, Store};
+    use forest_ipld::Ipld;
+
+    fn publish(
+        bs: &MemoryDB,
+        previous: Option<Cid>,
+        context: &str,
+        entry: &str,
+        is_rm: bool,
+    ) -> Cid {
+        if is_rm {
+            let rm = Advertisement {
+                IsRm: true,
+                ..test_ad(context)
+            };
+            put_test_ad(bs, previous, rm, &[])
+        } else {
+            put_test_ad(bs, previous, test_ad(context), &[entry])
+        }
+    }
+
+    fn entries(bs: &MemoryDB, ad_cid: Cid) -> Cid {
+        let ad: Advertisement = bs.get(&ad_cid).unwrap().unwrap();
+        match ad.Entries {
+            Some(Ipld::Link(cid)) => cid,
+            _ => panic!("ad has no entries"),
+        }
+    }
+
+    #[test]
+    fn test_prunable() {
+        let bs = MemoryDB::default();
+        let a = publish(&bs, None, "a", "a-entries", false);
+        let b = publish(&bs, Some(a), "b", "b-entries", false);
+        // Shares its entries with the ad for `a`.
+        let c = publish(&bs, Some(b), "c", "a-entries", false);
+        let rm_a = publish(&bs, Some(c), "a", "", true);
+        let rm_b = publish(&bs, Some(rm_a), "b", "", true);
+        let head = publish(&bs, Some(rm_b), "b", "b-entries-again", false);
+
+        let none = prunable(&bs, Some(head), &HashSet::new(), |_| false).unwrap();
+        assert!(none.is_empty());
+
+        let pruned = prunable(&bs, Some(head), &HashSet::new(), |_| true).unwrap();
+        assert_eq!(pruned, [entries(&bs, b)].into_iter().collect());
+
+        // Only `a` is past its grace period, but its entries are still used by `c`.
+        let pruned = prunable(&bs, Some(head), &HashSet::new(), |cid| *cid == rm_a).unwrap();
+        assert!(pruned.is_empty());
+
+        // Pruned entries are skipped rather than reported missing.
+        bs.delete(entries(&bs, b).to_bytes()).unwrap();
+        let already: HashSet<Cid> = [entries(&bs, b)].into_iter().collect();
+        assert!(prunable(&bs, Some(head), &already, |_| true)
+            .unwrap()
+            .is_empty());
+        assert!(prunable(&bs, Some(head), &HashSet::new(), |_| true).is_err());
+    }
+
+    #[test]
+    fn test_prunable_per_provider() {
+        let bs = MemoryDB::default();
+        let mine = publish(&bs, None, "deal", "mine", false);
+        let theirs = Advertisement {
+            Provider: "12D3KooWQYhTNQdmr3ArTeUHRYzFg94BKyTkoWBDWez9kSCVe2Xo".into(),
+            ..test_ad("deal")
+        };
+        let theirs = put_test_ad(&bs, Some(mine), theirs, &["theirs"]);
+        let head = publish(&bs, Some(theirs), "deal", "", true);
+
+        // Only the provider that published the removal loses its entries.
+        let pruned = prunable(&bs, Some(head), &HashSet::new(), |_| true).unwrap();
+        assert_eq!(pruned, [entries(&bs, mine)].into_iter().collect());
+    }
+}
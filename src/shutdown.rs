@@ -0,0 +1,121 @@
+//! Graceful shutdown: on SIGTERM or SIGINT the listeners stop accepting connections, new admin
+//! requests are turned away, and in-flight ones (such as a publish) get a chance to finish.
+use libp2p::futures::channel::oneshot;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tide::http::headers::{CONNECTION, RETRY_AFTER};
+use tide::{Middleware, Next, Request, StatusCode};
+
+/// Resolves with the signal number once the process receives SIGTERM or SIGINT.
+pub async fn signal() -> std::io::Result<i32> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT])?;
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            let _ = tx.send(signal);
+        }
+    });
+    rx.await
+        .map_err(|_| std::io::Error::other("signal handler stopped"))
+}
+
+#[derive(Default)]
+struct DrainState {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+/// Counts in-flight requests, and rejects new ones with 503 once draining has started.
+#[derive(Clone, Default)]
+pub struct Drain(Arc<DrainState>);
+
+/// Decrements the in-flight count when the request finishes, even if its future is dropped.
+struct InFlight(Arc<DrainState>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drain {
+    pub fn in_flight(&self) -> usize {
+        self.0.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting requests and waits for the in-flight ones to finish. Returns false if
+    /// some were still running after `timeout`.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.0.draining.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while self.in_flight() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+        true
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Drain {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        // Count the request before checking the flag, so `drain` can't miss it.
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+        let _in_flight = InFlight(self.0.clone());
+        if self.0.draining.load(Ordering::SeqCst) {
+            let mut res = tide::Response::new(StatusCode::ServiceUnavailable);
+            res.insert_header(CONNECTION, "close");
+            res.insert_header(RETRY_AFTER, "5");
+            res.set_error(tide::Error::from_str(
+                StatusCode::ServiceUnavailable,
+                "Shutting down",
+            ));
+            return Ok(res);
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide_testing::TideTestingExt;
+
+    #[test]
+    fn test_drain_waits_for_in_flight() -> Result<(), Box<dyn std::error::Error>> {
+        async_std::task::block_on(async {
+            let drain = Drain::default();
+            let (tx, rx) = async_std::channel::bounded::<()>(1);
+            let mut app = tide::new();
+            app.with(drain.clone());
+            app.at("/publish").post(move |_| {
+                let rx = rx.clone();
+                async move {
+                    rx.recv().await?;
+                    Ok("published")
+                }
+            });
+
+            let in_flight = {
+                let app = app.clone();
+                async_std::task::spawn(async move { app.post("/publish").recv_string().await })
+            };
+            while drain.in_flight() == 0 {
+                async_std::task::sleep(Duration::from_millis(1)).await;
+            }
+
+            assert!(!drain.drain(Duration::from_millis(20)).await);
+            let resp = app.post("/publish").send().await?;
+            assert_eq!(resp.status(), StatusCode::ServiceUnavailable);
+
+            tx.send(()).await?;
+            assert_eq!(in_flight.await?, "published");
+            assert!(drain.drain(Duration::from_secs(1)).await);
+            Ok(())
+        })
+    }
+}
@@ -1,12 +1,11 @@
+use crate::signer::{Signer, SignerError};
 use forest_cid as cid;
 use forest_cid::Cid;
-use libp2p::{
-    core::{identity::Keypair, PublicKey},
-    identity::error::SigningError,
-};
+use libp2p::core::PublicKey;
 use serde::{Deserialize, Serialize};
 use serde_json::Map;
 use serde_with::serde_as;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 #[serde_as]
@@ -18,6 +17,31 @@ pub struct SignedHead {
     sig: Vec<u8>,
     #[serde_as(as = "BytesAsMap")]
     pubkey: Vec<u8>,
+    /// Extended heads only. Goes up every time the head changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    /// Extended heads only. When the head was signed, in seconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+}
+
+/// The sequence number and signing time of an extended head.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Freshness {
+    pub seq: u64,
+    pub timestamp: u64,
+}
+
+/// What a reader requires of a head on top of a valid signature.
+#[derive(Debug, Clone, Default)]
+pub struct HeadCheck {
+    /// Sequence number of the last head accepted from this publisher. Heads with a lower one are
+    /// replays.
+    pub last_seq: Option<u64>,
+    /// Reject heads signed longer ago than this.
+    pub max_age: Option<Duration>,
+    /// Reject legacy heads, which can't be checked for either.
+    pub require_extended: bool,
 }
 
 #[derive(Debug, Error)]
@@ -26,27 +50,97 @@ pub enum SignedHeadError {
     InvalidSignature,
     #[error("Invalid public key")]
     InvalidPublicKey,
+    #[error("Head has only one of seq and timestamp")]
+    IncompleteExtension,
+    #[error("Head has no seq or timestamp")]
+    NotExtended,
+    #[error("Head seq {seq} is older than the last seen {last_seq}")]
+    Replayed { seq: u64, last_seq: u64 },
+    #[error("Head was signed {0:?} ago")]
+    Stale(Duration),
+}
+
+fn unix_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
 }
 
 impl SignedHead {
-    pub fn new(signing_key: &Keypair, cid: Cid) -> Result<Self, SigningError> {
-        let sig = signing_key.sign(&cid.to_bytes())?;
+    /// Signs the head. With a `seq` this is an extended head, which also signs the sequence number
+    /// and the current time so readers can reject replayed or stale heads.
+    pub async fn new(signer: &dyn Signer, cid: Cid, seq: Option<u64>) -> Result<Self, SignerError> {
+        let freshness = seq.map(|seq| Freshness {
+            seq,
+            timestamp: unix_now().as_secs(),
+        });
+        Self::sign(signer, cid, freshness).await
+    }
+
+    pub(crate) async fn sign(
+        signer: &dyn Signer,
+        cid: Cid,
+        freshness: Option<Freshness>,
+    ) -> Result<Self, SignerError> {
+        let sig = signer.sign(&Self::payload(&cid, freshness)).await?;
         Ok(SignedHead {
             head: cid,
-            pubkey: signing_key.public().to_protobuf_encoding(),
+            pubkey: signer.public().to_protobuf_encoding(),
             sig,
+            seq: freshness.map(|f| f.seq),
+            timestamp: freshness.map(|f| f.timestamp),
         })
     }
 
+    /// Legacy heads sign the cid alone. Extended heads append the seq and timestamp, each as a big
+    /// endian u64.
+    fn payload(cid: &Cid, freshness: Option<Freshness>) -> Vec<u8> {
+        let mut payload = cid.to_bytes();
+        if let Some(freshness) = freshness {
+            payload.extend_from_slice(&freshness.seq.to_be_bytes());
+            payload.extend_from_slice(&freshness.timestamp.to_be_bytes());
+        }
+        payload
+    }
+
+    /// Checks the signature, of either a legacy or an extended head.
     pub fn open(self) -> Result<(PublicKey, Cid), SignedHeadError> {
+        let (pk, cid, _) = self.open_checked(&HeadCheck::default())?;
+        Ok((pk, cid))
+    }
+
+    /// Checks the signature, and that the head meets `check`. Returns the seq and timestamp of an
+    /// extended head.
+    pub fn open_checked(
+        self,
+        check: &HeadCheck,
+    ) -> Result<(PublicKey, Cid, Option<Freshness>), SignedHeadError> {
+        let freshness = match (self.seq, self.timestamp) {
+            (Some(seq), Some(timestamp)) => Some(Freshness { seq, timestamp }),
+            (None, None) => None,
+            _ => return Err(SignedHeadError::IncompleteExtension),
+        };
         let pk = PublicKey::from_protobuf_encoding(&self.pubkey)
             .map_err(|_| SignedHeadError::InvalidPublicKey)?;
-        let valid = pk.verify(&self.head.to_bytes(), &self.sig);
+        let valid = pk.verify(&Self::payload(&self.head, freshness), &self.sig);
         if !valid {
             return Err(SignedHeadError::InvalidSignature);
         }
 
-        Ok((pk, self.head))
+        match freshness {
+            None if check.require_extended => return Err(SignedHeadError::NotExtended),
+            None => {}
+            Some(Freshness { seq, timestamp }) => {
+                if let Some(last_seq) = check.last_seq.filter(|&last_seq| seq < last_seq) {
+                    return Err(SignedHeadError::Replayed { seq, last_seq });
+                }
+                let age = unix_now().saturating_sub(Duration::from_secs(timestamp));
+                if check.max_age.is_some_and(|max_age| age > max_age) {
+                    return Err(SignedHeadError::Stale(age));
+                }
+            }
+        }
+        Ok((pk, self.head, freshness))
     }
 }
 
@@ -101,6 +195,7 @@ fn from_map_to_cid(value: serde_json::Map<String, serde_json::Value>) -> Result<
 #[cfg(test)]
 mod tests {
     use super::*;
+    use libp2p::identity::Keypair;
 
     #[test]
     fn test_decode_signed_msg() {
@@ -119,12 +214,32 @@ mod tests {
         println!("{:?}", pk);
     }
 
+    #[test]
+    fn test_decode_other_key_types() {
+        // Signed with secp256k1 and RSA keys, see importer-helper/cmd/interop-vectors.
+        for vector in [
+            include_str!("../testdata/interop-secp256k1.json"),
+            include_str!("../testdata/interop-rsa.json"),
+        ] {
+            let vector: serde_json::Value = serde_json::from_str(vector).unwrap();
+            let signed_head: SignedHead =
+                serde_json::from_str(vector["signed_head"].as_str().unwrap())
+                    .expect("deser failed");
+            let (pk, _) = signed_head.open().expect("failed to open signed_head");
+            assert_eq!(
+                libp2p::PeerId::from_public_key(&pk).to_base58(),
+                vector["peer_id"]
+            );
+        }
+    }
+
     #[test]
     fn test_sign_head() {
         let kp = Keypair::generate_ed25519();
         let cid = Cid::try_from("bafybeicyhbhhklw3kdwgrxmf67mhkgjbsjauphsvrzywav63kn7bkpmqfa")
             .expect("failed to parse cid");
-        let signed_head = SignedHead::new(&kp, cid).expect("failed to sign head");
+        let signed_head = async_std::task::block_on(SignedHead::new(&kp, cid, None))
+            .expect("failed to sign head");
         let signed_head_encoded = serde_json::to_string(&signed_head).expect("ser failed");
         let signed_head: SignedHead =
             serde_json::from_str(&signed_head_encoded).expect("deser failed");
@@ -134,4 +249,71 @@ mod tests {
         assert_eq!(head, cid);
         assert_eq!(pk, kp.public());
     }
+
+    #[test]
+    fn test_extended_head() {
+        let kp = Keypair::generate_ed25519();
+        let cid = Cid::try_from("bafybeicyhbhhklw3kdwgrxmf67mhkgjbsjauphsvrzywav63kn7bkpmqfa")
+            .expect("failed to parse cid");
+        let signed_head = async_std::task::block_on(SignedHead::new(&kp, cid, Some(7)))
+            .expect("failed to sign head");
+        let encoded = serde_json::to_string(&signed_head).expect("ser failed");
+        let check = HeadCheck {
+            last_seq: Some(7),
+            max_age: Some(Duration::from_secs(60)),
+            require_extended: true,
+        };
+        let (pk, head, freshness) = serde_json::from_str::<SignedHead>(&encoded)
+            .unwrap()
+            .open_checked(&check)
+            .expect("failed to open signed_head");
+        assert_eq!((pk, head), (kp.public(), cid));
+        assert_eq!(freshness.unwrap().seq, 7);
+
+        // The seq and timestamp are covered by the signature.
+        let mut tampered: SignedHead = serde_json::from_str(&encoded).unwrap();
+        tampered.seq = Some(8);
+        assert!(matches!(
+            tampered.open(),
+            Err(SignedHeadError::InvalidSignature)
+        ));
+        let mut tampered: SignedHead = serde_json::from_str(&encoded).unwrap();
+        tampered.timestamp = None;
+        assert!(matches!(
+            tampered.open(),
+            Err(SignedHeadError::IncompleteExtension)
+        ));
+
+        let replayed = HeadCheck {
+            last_seq: Some(8),
+            ..HeadCheck::default()
+        };
+        assert!(matches!(
+            serde_json::from_str::<SignedHead>(&encoded)
+                .unwrap()
+                .open_checked(&replayed),
+            Err(SignedHeadError::Replayed {
+                seq: 7,
+                last_seq: 8
+            })
+        ));
+
+        let old = Freshness {
+            seq: 7,
+            timestamp: unix_now().as_secs() - 600,
+        };
+        let stale = async_std::task::block_on(SignedHead::sign(&kp, cid, Some(old))).unwrap();
+        assert!(matches!(
+            stale.open_checked(&check),
+            Err(SignedHeadError::Stale(_))
+        ));
+
+        // Legacy heads still open, unless extended ones are required.
+        let legacy = async_std::task::block_on(SignedHead::new(&kp, cid, None)).unwrap();
+        assert!(!serde_json::to_string(&legacy).unwrap().contains("seq"));
+        assert!(matches!(
+            legacy.open_checked(&check),
+            Err(SignedHeadError::NotExtended)
+        ));
+    }
 }
@@ -0,0 +1,388 @@
+//! Signing on behalf of a libp2p identity, either with a key held in this process or by a signer
+//! service that keeps the key to itself, such as a wallet daemon.
+//!
+//! The signer service is spoken to over HTTP, on a TCP port, with or without TLS, or a Unix socket:
+//!
+//! - `GET /public_key` returns `{"public_key": "<base64 protobuf public key>"}`.
+//! - `POST /sign` with `{"data": "<base64>"}` returns `{"signature": "<base64>"}`.
+use crate::protobuf;
+use crate::transport::{Endpoint, TransportError};
+use libp2p::identity::error::SigningError;
+use libp2p::identity::{Keypair, PublicKey};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+use tide::http::{Body, Method, Request};
+
+#[derive(Error, Debug)]
+pub enum SignerError {
+    #[error("Failed to sign: {0}")]
+    Local(#[from] SigningError),
+    #[error("Signer request failed: {0}")]
+    Transport(#[from] TransportError),
+    #[error("Signer did not answer within {0:?}")]
+    Timeout(Duration),
+    #[error("Invalid response from the signer: {0}")]
+    InvalidResponse(String),
+    #[error("Signer returned a signature that doesn't verify against its public key")]
+    InvalidSignature,
+}
+
+#[async_trait::async_trait]
+pub trait Signer: Send + Sync {
+    /// The key that verifies this signer's signatures.
+    fn public(&self) -> PublicKey;
+
+    async fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, SignerError>;
+}
+
+#[async_trait::async_trait]
+impl Signer for Keypair {
+    fn public(&self) -> PublicKey {
+        Keypair::public(self)
+    }
+
+    async fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, SignerError> {
+        Ok(Keypair::sign(self, msg)?)
+    }
+}
+
+/// Signs `payload` and returns the protobuf encoded libp2p signed envelope, the same bytes
+/// `SignedEnvelope::new(..).into_protobuf_encoding()` produces, but without needing the key.
+pub async fn seal(
+    signer: &dyn Signer,
+    domain: &str,
+    payload_type: &[u8],
+    payload: &[u8],
+) -> Result<Vec<u8>, SignerError> {
+    let mut unsigned = vec![];
+    for part in [domain.as_bytes(), payload_type, payload] {
+        protobuf::put_varint(&mut unsigned, part.len() as u64);
+        unsigned.extend_from_slice(part);
+    }
+    let signature = signer.sign(&unsigned).await?;
+
+    let mut envelope = vec![];
+    protobuf::put_bytes(&mut envelope, 1, &signer.public().to_protobuf_encoding());
+    protobuf::put_bytes(&mut envelope, 2, payload_type);
+    protobuf::put_bytes(&mut envelope, 3, payload);
+    protobuf::put_bytes(&mut envelope, 5, &signature);
+    Ok(envelope)
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteSignerConfig {
+    /// `http://host:port`, `https://host:port` or `http+unix:///path/to/socket`.
+    pub url: String,
+    /// PEM file with CA certificates to trust besides the usual roots, for an `https` signer with a
+    /// certificate from a private CA.
+    #[serde(default)]
+    pub ca_path: Option<PathBuf>,
+    /// How long to wait for each request to the signer.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    5
+}
+
+#[derive(Serialize, Deserialize)]
+struct PublicKeyResponse {
+    public_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignRequest {
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+/// A signer service holding the key. Its public key is fetched once, on connecting.
+pub struct RemoteSigner {
+    endpoint: Endpoint,
+    timeout: Duration,
+    public: PublicKey,
+}
+
+impl RemoteSigner {
+    pub async fn connect(config: &RemoteSignerConfig) -> Result<Self, SignerError> {
+        let mut endpoint = Endpoint::new(&config.url)?;
+        if let Some(path) = &config.ca_path {
+            endpoint = endpoint.with_ca(path)?;
+        }
+        let timeout = Duration::from_secs(config.timeout_secs);
+        let res: PublicKeyResponse =
+            call(&endpoint, timeout, Method::Get, "/public_key", None).await?;
+        let public = base64::decode(&res.public_key)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| PublicKey::from_protobuf_encoding(&bytes).map_err(|e| e.to_string()))
+            .map_err(SignerError::InvalidResponse)?;
+        Ok(RemoteSigner {
+            endpoint,
+            timeout,
+            public,
+        })
+    }
+}
+
+/// Sends a request to the signer, failing if there is no answer within `timeout`.
+async fn call<T: DeserializeOwned>(
+    endpoint: &Endpoint,
+    timeout: Duration,
+    method: Method,
+    path: &str,
+    body: Option<Body>,
+) -> Result<T, SignerError> {
+    let res = async_std::future::timeout(timeout, async {
+        let mut req = Request::new(method, endpoint.url(path)?);
+        if let Some(body) = body {
+            req.set_body(body);
+        }
+        let mut res = endpoint.send(req).await?;
+        res.body_json::<T>()
+            .await
+            .map_err(|e| SignerError::InvalidResponse(e.to_string()))
+    })
+    .await;
+    res.map_err(|_| SignerError::Timeout(timeout))?
+}
+
+#[async_trait::async_trait]
+impl Signer for RemoteSigner {
+    fn public(&self) -> PublicKey {
+        self.public.clone()
+    }
+
+    async fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, SignerError> {
+        let body = Body::from_json(&SignRequest {
+            data: base64::encode(msg),
+        })
+        .map_err(|e| TransportError::Http(e.to_string()))?;
+        let res: SignResponse = call(
+            &self.endpoint,
+            self.timeout,
+            Method::Post,
+            "/sign",
+            Some(body),
+        )
+        .await?;
+        let signature = base64::decode(&res.signature)
+            .map_err(|e| SignerError::InvalidResponse(e.to_string()))?;
+        // Catches a signer holding some other key than the one it advertised.
+        if !self.public.verify(msg, &signature) {
+            return Err(SignerError::InvalidSignature);
+        }
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::signed_head::SignedHead;
+    use async_std::net::TcpListener;
+    use async_std::prelude::*;
+    use forest_cid::Cid;
+    use libp2p::core::SignedEnvelope;
+
+    /// An in-process signer service. Signs with `signing_key`, which needn't be the key it
+    /// advertises.
+    fn stand_in(public_key: PublicKey, signing_key: Keypair) -> tide::Server<Keypair> {
+        let mut app = tide::with_state(signing_key);
+        app.at("/public_key").get(move |_: tide::Request<Keypair>| {
+            let public_key = base64::encode(public_key.to_protobuf_encoding());
+            async move { Body::from_json(&PublicKeyResponse { public_key }) }
+        });
+        app.at("/sign")
+            .post(|mut r: tide::Request<Keypair>| async move {
+                let req: SignRequest = r.body_json().await?;
+                let sig = r.state().sign(&base64::decode(&req.data)?)?;
+                Body::from_json(&SignResponse {
+                    signature: base64::encode(sig),
+                })
+            });
+        app
+    }
+
+    /// Serves `app` on a local port and returns its url.
+    pub(crate) async fn serve<State: Clone + Send + Sync + 'static>(
+        app: tide::Server<State>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        async_std::task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let app = app.clone();
+                async_std::task::spawn(async move {
+                    let _ = async_h1::accept(stream, |req| app.respond(req)).await;
+                });
+            }
+        });
+        url
+    }
+
+    /// A remote signer backed by an in-process stand-in holding `key`.
+    pub(crate) async fn remote_signer(key: Keypair) -> RemoteSigner {
+        let url = serve(stand_in(key.public(), key)).await;
+        RemoteSigner::connect(&RemoteSignerConfig {
+            url,
+            ca_path: None,
+            timeout_secs: 5,
+        })
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn test_seal_matches_libp2p() {
+        let key = Keypair::generate_ed25519();
+        let sealed =
+            async_std::task::block_on(seal(&key, "indexer", b"/codec", b"payload")).unwrap();
+        let expected = SignedEnvelope::new(
+            key.clone(),
+            "indexer".into(),
+            b"/codec".to_vec(),
+            b"payload".to_vec(),
+        )
+        .unwrap()
+        .into_protobuf_encoding();
+        assert_eq!(sealed, expected);
+        let envelope = SignedEnvelope::from_protobuf_encoding(&sealed).unwrap();
+        assert_eq!(
+            envelope.payload("indexer".into(), b"/codec").unwrap(),
+            b"payload"
+        );
+    }
+
+    #[test]
+    fn test_remote_signer() {
+        async_std::task::block_on(async {
+            let key = Keypair::generate_ed25519();
+            let signer = remote_signer(key.clone()).await;
+            assert_eq!(signer.public(), key.public());
+
+            let cid = Cid::try_from("bafybeicyhbhhklw3kdwgrxmf67mhkgjbsjauphsvrzywav63kn7bkpmqfa")
+                .unwrap();
+            let (pk, head) = SignedHead::new(&signer, cid, None)
+                .await
+                .unwrap()
+                .open()
+                .unwrap();
+            assert_eq!((pk, head), (key.public(), cid));
+
+            let envelope = seal(&signer, "indexer", b"/codec", b"payload")
+                .await
+                .unwrap();
+            let envelope = SignedEnvelope::from_protobuf_encoding(&envelope).unwrap();
+            assert!(envelope.payload("indexer".into(), b"/codec").is_ok());
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remote_signer_over_unix_socket() {
+        async_std::task::block_on(async {
+            let path =
+                std::env::temp_dir().join(format!("signer-{:016x}.sock", rand::random::<u64>()));
+            let listener = async_std::os::unix::net::UnixListener::bind(&path)
+                .await
+                .unwrap();
+            let key = Keypair::generate_ed25519();
+            let app = stand_in(key.public(), key.clone());
+            async_std::task::spawn(async move {
+                let mut incoming = listener.incoming();
+                while let Some(Ok(stream)) = incoming.next().await {
+                    let app = app.clone();
+                    async_std::task::spawn(async move {
+                        let _ = async_h1::accept(stream, |req| app.respond(req)).await;
+                    });
+                }
+            });
+
+            let signer = RemoteSigner::connect(&RemoteSignerConfig {
+                url: format!("http+unix://{}", path.display()),
+                ca_path: None,
+                timeout_secs: 5,
+            })
+            .await
+            .unwrap();
+            let sig = signer.sign(b"hello").await.unwrap();
+            assert!(key.public().verify(b"hello", &sig));
+            std::fs::remove_file(&path).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_remote_signer_errors() {
+        async_std::task::block_on(async {
+            // Advertises one key but signs with another.
+            let url = serve(stand_in(
+                Keypair::generate_ed25519().public(),
+                Keypair::generate_ed25519(),
+            ))
+            .await;
+            let config = RemoteSignerConfig {
+                url,
+                ca_path: None,
+                timeout_secs: 5,
+            };
+            let signer = RemoteSigner::connect(&config).await.unwrap();
+            assert!(matches!(
+                signer.sign(b"hello").await,
+                Err(SignerError::InvalidSignature)
+            ));
+
+            let mut app = tide::new();
+            app.at("/public_key").get(|_: tide::Request<()>| async {
+                Err::<String, _>(tide::Error::from_str(
+                    tide::StatusCode::ServiceUnavailable,
+                    "wallet is locked",
+                ))
+            });
+            let config = RemoteSignerConfig {
+                url: serve(app).await,
+                ca_path: None,
+                timeout_secs: 5,
+            };
+            assert!(matches!(
+                RemoteSigner::connect(&config).await,
+                Err(SignerError::Transport(TransportError::Status(503, _)))
+            ));
+
+            let mut app = tide::new();
+            app.at("/public_key").get(|_: tide::Request<()>| async {
+                async_std::task::sleep(Duration::from_secs(10)).await;
+                Ok::<_, tide::Error>("")
+            });
+            let config = RemoteSignerConfig {
+                url: serve(app).await,
+                ca_path: None,
+                timeout_secs: 1,
+            };
+            assert!(matches!(
+                RemoteSigner::connect(&config).await,
+                Err(SignerError::Timeout(_))
+            ));
+
+            // Nothing listens on port 1.
+            let config = RemoteSignerConfig {
+                url: "http://127.0.0.1:1".into(),
+                ca_path: None,
+                timeout_secs: 5,
+            };
+            assert!(matches!(
+                RemoteSigner::connect(&config).await,
+                Err(SignerError::Transport(TransportError::Connect(_)))
+            ));
+        })
+    }
+}
@@ -0,0 +1,216 @@
+use forest_cid::Cid;
+use forest_db::{Error, Store};
+use ipld_blockstore::BlockStore;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Wraps a store and keeps an index of the keys written through it, along with the size of each
+/// value. The underlying stores can't be enumerated, and the index lets us report the blockstore
+/// size.
+#[derive(Debug, Default)]
+pub struct IndexedStore<S> {
+    inner: S,
+    index: RwLock<HashMap<Vec<u8>, usize>>,
+}
+
+impl<S> IndexedStore<S> {
+    pub fn new(inner: S) -> Self {
+        IndexedStore {
+            inner,
+            index: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Number of blocks in the store.
+    pub fn block_count(&self) -> usize {
+        self.index.read().unwrap().len()
+    }
+
+    /// Total size of all blocks in the store, in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.index
+            .read()
+            .unwrap()
+            .values()
+            .map(|len| *len as u64)
+            .sum()
+    }
+}
+
+impl<S> IndexedStore<S> {
+    /// Cids of every block in the store.
+    pub fn cids(&self) -> Vec<Cid> {
+        self.index
+            .read()
+            .unwrap()
+            .keys()
+            .filter_map(|key| Cid::read_bytes(key.as_slice()).ok())
+            .collect()
+    }
+
+    /// Size of the block in bytes, if it is in the store.
+    pub fn block_size(&self, cid: &Cid) -> Option<usize> {
+        self.index.read().unwrap().get(&cid.to_bytes()).copied()
+    }
+}
+
+impl<S: Clone> Clone for IndexedStore<S> {
+    fn clone(&self) -> Self {
+        IndexedStore {
+            inner: self.inner.clone(),
+            index: RwLock::new(self.index.read().unwrap().clone()),
+        }
+    }
+}
+
+impl<S: Store> Store for IndexedStore<S> {
+    fn read<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.read(key)
+    }
+
+    fn write<K, V>(&self, key: K, value: V) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.inner.write(key.as_ref(), value.as_ref())?;
+        self.index
+            .write()
+            .unwrap()
+            .insert(key.as_ref().to_vec(), value.as_ref().len());
+        Ok(())
+    }
+
+    fn delete<K>(&self, key: K) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.delete(key.as_ref())?;
+        self.index.write().unwrap().remove(key.as_ref());
+        Ok(())
+    }
+
+    fn exists<K>(&self, key: K) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.exists(key)
+    }
+}
+
+impl<S: Store> BlockStore for IndexedStore<S> {}
+
+/// A namespace in a store shared by several chains. Every key is prefixed, so a chain can't see
+/// or delete another chain's blocks, even when both hold the same block.
+#[derive(Debug)]
+pub struct Namespaced<S> {
+    inner: Arc<S>,
+    prefix: Vec<u8>,
+}
+
+impl<S> Namespaced<S> {
+    pub fn new(inner: Arc<S>, prefix: impl Into<Vec<u8>>) -> Self {
+        Namespaced {
+            inner,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        [self.prefix.as_slice(), key].concat()
+    }
+}
+
+impl<S> Clone for Namespaced<S> {
+    fn clone(&self) -> Self {
+        Namespaced {
+            inner: self.inner.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+impl<S: Store> Store for Namespaced<S> {
+    fn read<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.read(self.key(key.as_ref()))
+    }
+
+    fn write<K, V>(&self, key: K, value: V) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.inner.write(self.key(key.as_ref()), value)
+    }
+
+    fn delete<K>(&self, key: K) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.delete(self.key(key.as_ref()))
+    }
+
+    fn exists<K>(&self, key: K) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.exists(self.key(key.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forest_db::MemoryDB;
+    use forest_ipld::Ipld;
+
+    #[test]
+    fn test_index_tracks_writes() {
+        let bs = IndexedStore::new(MemoryDB::default());
+        let a = bs
+            .put(&Ipld::String("a".into()), forest_cid::Code::Blake2b256)
+            .unwrap();
+        let b = bs
+            .put(&Ipld::String("bb".into()), forest_cid::Code::Blake2b256)
+            .unwrap();
+        // Writing the same block again doesn't count twice.
+        bs.put(&Ipld::String("a".into()), forest_cid::Code::Blake2b256)
+            .unwrap();
+
+        assert_eq!(bs.block_count(), 2);
+        let mut cids = bs.cids();
+        cids.sort_by_key(|c| c.to_bytes());
+        let mut expected = vec![a, b];
+        expected.sort_by_key(|c| c.to_bytes());
+        assert_eq!(cids, expected);
+        bs.delete(a.to_bytes()).unwrap();
+        assert_eq!(bs.block_count(), 1);
+        assert_eq!(
+            bs.total_bytes(),
+            bs.get_bytes(&b).unwrap().unwrap().len() as u64
+        );
+        assert!(bs.get_bytes(&a).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_namespaces_are_separate() {
+        let db = Arc::new(MemoryDB::default());
+        let first = IndexedStore::new(Namespaced::new(db.clone(), "/p/first/"));
+        let second = IndexedStore::new(Namespaced::new(db, "/p/second/"));
+        let block = Ipld::String("shared".into());
+        let cid = first.put(&block, forest_cid::Code::Blake2b256).unwrap();
+        assert!(second.get_bytes(&cid).unwrap().is_none());
+
+        second.put(&block, forest_cid::Code::Blake2b256).unwrap();
+        first.delete(cid.to_bytes()).unwrap();
+        assert!(first.get_bytes(&cid).unwrap().is_none());
+        assert!(second.get_bytes(&cid).unwrap().is_some());
+        assert_eq!(second.cids(), vec![cid]);
+    }
+}
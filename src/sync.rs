@@ -0,0 +1,454 @@
+//! Syncs a provider's chain the way an indexer does: from the public head, back to the last ad it
+//! has seen, checking every block and signature on the way.
+use crate::advertisement::{Advertisement, EntryChunk};
+use crate::chain::{self, ChainError};
+use crate::client::ClientError;
+use crate::signed_head::{HeadCheck, SignedHead, SignedHeadError};
+use crate::transport::{Endpoint, TransportError};
+use crate::verify::{self, BlockError};
+use forest_cid::Cid;
+use forest_ipld::Ipld;
+use libp2p::futures::stream::{self, Stream, StreamExt};
+use libp2p::PeerId;
+use multihash::Multihash;
+use std::path::Path;
+use thiserror::Error;
+use tide::http::{Method, Request};
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error("Invalid head: {0}")]
+    Head(#[from] SignedHeadError),
+    #[error("Head is signed by {0}, expected {1}")]
+    WrongPublisher(Box<PeerId>, Box<PeerId>),
+    #[error("Block {0}: {1}")]
+    Block(Cid, BlockError),
+    #[error("Block {0} is not a valid {1}: {2}")]
+    Decoding(Cid, &'static str, String),
+    #[error("Invalid multihash in {0}")]
+    InvalidMultihash(Cid),
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+}
+
+/// What one ad tells an indexer: the multihashes to add to, or remove from, its context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncEvent {
+    pub ad: Cid,
+    pub context_id: Vec<u8>,
+    pub is_rm: bool,
+    /// Empty for removals, and for ads whose entries the provider has pruned.
+    pub multihashes: Vec<Multihash>,
+}
+
+/// Reads a chain from a provider's public port.
+#[derive(Debug, Clone)]
+pub struct SyncClient {
+    public: Endpoint,
+    publisher: Option<PeerId>,
+    check: HeadCheck,
+}
+
+impl SyncClient {
+    /// Takes the base url of the public app, e.g. `http://127.0.0.1:8070`, or an `https` or
+    /// `http+unix` url.
+    pub fn new(public_url: &str) -> Result<Self, SyncError> {
+        Ok(SyncClient {
+            public: Endpoint::new(public_url).map_err(ClientError::from)?,
+            publisher: None,
+            check: HeadCheck::default(),
+        })
+    }
+
+    /// Trusts the CA certificates in the PEM file at `path`, for a provider with a certificate
+    /// from a private CA.
+    pub fn with_ca(self, path: &Path) -> Result<Self, SyncError> {
+        Ok(SyncClient {
+            public: self.public.with_ca(path).map_err(ClientError::from)?,
+            ..self
+        })
+    }
+
+    /// Only accept heads signed by `publisher`.
+    pub fn with_publisher(self, publisher: PeerId) -> Self {
+        SyncClient {
+            publisher: Some(publisher),
+            ..self
+        }
+    }
+
+    /// Checks heads for replays and staleness as well as their signature.
+    pub fn with_head_check(self, check: HeadCheck) -> Self {
+        SyncClient { check, ..self }
+    }
+
+    /// Fetches and opens the signed head. `None` if nothing has been published yet.
+    pub async fn head(&self) -> Result<Option<(PeerId, Cid)>, SyncError> {
+        let mut res = match self.get("head").await {
+            Ok(res) => res,
+            Err(SyncError::Client(ClientError::Transport(TransportError::Status(404, _)))) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        let signed_head: SignedHead = res
+            .body_json()
+            .await
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        let (pk, head, _) = signed_head.open_checked(&self.check)?;
+        let signer = PeerId::from_public_key(&pk);
+        match self.publisher {
+            Some(publisher) if publisher != signer => Err(SyncError::WrongPublisher(
+                Box::new(signer),
+                Box::new(publisher),
+            )),
+            _ => Ok(Some((signer, head))),
+        }
+    }
+
+    /// Fetches the ads from `head` back to `last_seen`, newest first, checking each one's block and
+    /// signature. If `last_seen` isn't in the chain, as after a compaction, this goes back to the
+    /// start.
+    pub async fn ads_since(
+        &self,
+        head: Cid,
+        last_seen: Option<Cid>,
+    ) -> Result<Vec<(Cid, Advertisement)>, SyncError> {
+        let mut ads = vec![];
+        let mut next = Some(head);
+        while let Some(cid) = next.filter(|&cid| Some(cid) != last_seen) {
+            let ad = self.ad(cid).await?;
+            next = chain::link(&ad.PreviousID, cid)?;
+            ads.push((cid, ad));
+        }
+        Ok(ads)
+    }
+
+    /// Fetches one ad, checking its block and signature.
+    pub async fn ad(&self, cid: Cid) -> Result<Advertisement, SyncError> {
+        let ad: Advertisement = self.fetch(cid, "advertisement").await?;
+        ad.verify_sig()
+            .map_err(|e| ChainError::BadSignature(cid, e))?;
+        Ok(ad)
+    }
+
+    /// Syncs everything published after `last_seen`. Returns the head to pass as `last_seen` next
+    /// time, and the events of the new ads, oldest first. Entries are fetched as the events are
+    /// read.
+    pub async fn sync(
+        &self,
+        last_seen: Option<Cid>,
+    ) -> Result<
+        (
+            Option<Cid>,
+            impl Stream<Item = Result<SyncEvent, SyncError>> + '_,
+        ),
+        SyncError,
+    > {
+        let (head, ads) = match self.head().await? {
+            Some((_, head)) => (Some(head), self.ads_since(head, last_seen).await?),
+            None => (None, vec![]),
+        };
+        let events = stream::iter(ads.into_iter().rev()).then(move |(cid, ad)| self.event(cid, ad));
+        Ok((head.or(last_seen), events))
+    }
+
+    async fn event(&self, cid: Cid, ad: Advertisement) -> Result<SyncEvent, SyncError> {
+        let context_id = match ad.ContextID {
+            Ipld::Bytes(bytes) => bytes,
+            _ => {
+                return Err(SyncError::Decoding(
+                    cid,
+                    "advertisement",
+                    "ContextID is not bytes".into(),
+                ))
+            }
+        };
+        let mut multihashes = vec![];
+        let mut next = chain::link(&ad.Entries, cid)?;
+        while let Some(chunk_cid) = next {
+            let chunk: EntryChunk = match self.fetch(chunk_cid, "entry chunk").await {
+                Ok(chunk) => chunk,
+                // Entries of removed contexts may have been pruned. The removal follows later in
+                // the chain, so there's nothing to add.
+                Err(SyncError::Client(ClientError::Transport(TransportError::Status(
+                    404,
+                    msg,
+                )))) if msg == crate::ENTRIES_UNAVAILABLE => break,
+                Err(e) => return Err(e),
+            };
+            for entry in chunk.Entries {
+                let mh = match entry {
+                    Ipld::Bytes(bytes) => Multihash::from_bytes(&bytes).ok(),
+                    _ => None,
+                };
+                multihashes.push(mh.ok_or(SyncError::InvalidMultihash(chunk_cid))?);
+            }
+            next = chain::link(&chunk.Next, chunk_cid)?;
+        }
+        Ok(SyncEvent {
+            ad: cid,
+            context_id,
+            is_rm: ad.IsRm,
+            multihashes,
+        })
+    }
+
+    /// Fetches a block and checks it against its cid before decoding it.
+    async fn fetch<T: serde::de::DeserializeOwned>(
+        &self,
+        cid: Cid,
+        kind: &'static str,
+    ) -> Result<T, SyncError> {
+        let mut res = self.get(&cid.to_string()).await?;
+        let bytes = res
+            .body_bytes()
+            .await
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        verify::verify_block(&cid, &bytes).map_err(|e| SyncError::Block(cid, e))?;
+        forest_encoding::from_slice(&bytes)
+            .map_err(|e| SyncError::Decoding(cid, kind, e.to_string()))
+    }
+
+    async fn get(&self, path: &str) -> Result<tide::http::Response, SyncError> {
+        let url = self.public.url(path).map_err(ClientError::from)?;
+        let res = self.public.send(Request::new(Method::Get, url)).await;
+        Ok(res.map_err(ClientError::from)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ProviderClient;
+    use crate::signed_head::Freshness;
+    use crate::signer::tests::serve;
+    use crate::tests::{test_ad, TEST_PEER_ID};
+    use forest_db::Store;
+    use ipld_blockstore::BlockStore;
+    use multihash::MultihashDigest;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tide::Body;
+
+    async fn publish(client: &ProviderClient, context: &str, count: usize) -> Vec<Multihash> {
+        let id = client.create(&test_ad(context)).await.unwrap();
+        let mhs: Vec<Multihash> = (0..count)
+            .map(|i| multihash::Code::Sha2_256.digest(format!("{}-{}", context, i).as_bytes()))
+            .collect();
+        // Two entries per chunk, so the chunks are linked.
+        for chunk in mhs.chunks(2) {
+            client.add_entries(id, chunk).await.unwrap();
+        }
+        client.publish(id).await.unwrap();
+        mhs
+    }
+
+    async fn collect(
+        events: impl Stream<Item = Result<SyncEvent, SyncError>>,
+    ) -> Result<Vec<SyncEvent>, SyncError> {
+        events.collect::<Vec<_>>().await.into_iter().collect()
+    }
+
+    #[test]
+    fn test_sync() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider();
+            let publisher = provider.keys.read().await.peer_id();
+            let url = serve(crate::tests::test_app(provider.clone())).await;
+            let client = ProviderClient::new(&url, &url).unwrap();
+            let sync = SyncClient::new(&url).unwrap().with_publisher(publisher);
+
+            let (head, events) = sync.sync(None).await.unwrap();
+            assert!(head.is_none());
+            assert!(collect(events).await.unwrap().is_empty());
+
+            let first = publish(&client, "first", 3).await;
+            let second = publish(&client, "second", 1).await;
+            let (head, events) = sync.sync(None).await.unwrap();
+            let events = collect(events).await.unwrap();
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[0].context_id, b"first");
+            assert_eq!(events[0].multihashes.len(), 3);
+            assert!(events[0].multihashes.iter().all(|mh| first.contains(mh)));
+            assert_eq!(events[1].multihashes, second);
+            assert_eq!(Some(events[1].ad), head);
+
+            // Only what was published since the last sync.
+            let removal = client.remove(TEST_PEER_ID, b"first").await.unwrap();
+            let (next_head, events) = sync.sync(head).await.unwrap();
+            assert_eq!(next_head, Some(removal));
+            assert_eq!(
+                collect(events).await.unwrap(),
+                vec![SyncEvent {
+                    ad: removal,
+                    context_id: b"first".to_vec(),
+                    is_rm: true,
+                    multihashes: vec![],
+                }]
+            );
+            let (_, events) = sync.sync(next_head).await.unwrap();
+            assert!(collect(events).await.unwrap().is_empty());
+
+            let other = SyncClient::new(&url)
+                .unwrap()
+                .with_publisher(PeerId::random());
+            assert!(matches!(
+                other.head().await,
+                Err(SyncError::WrongPublisher(..))
+            ));
+        })
+    }
+
+    #[test]
+    fn test_sync_across_compaction() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider();
+            let url = serve(crate::tests::test_app(provider.clone())).await;
+            let client = ProviderClient::new(&url, &url).unwrap();
+            let sync = SyncClient::new(&url).unwrap();
+
+            publish(&client, "removed", 1).await;
+            publish(&client, "kept", 1).await;
+            let (last_seen, events) = sync.sync(None).await.unwrap();
+            assert_eq!(collect(events).await.unwrap().len(), 2);
+
+            // The removal comes after the last sync, and compaction drops the ad it removes along
+            // with the ad the indexer saw last.
+            client.remove(TEST_PEER_ID, b"removed").await.unwrap();
+            client.admin(Method::Post, "compact", None).await.unwrap();
+
+            let (_, events) = sync.sync(last_seen).await.unwrap();
+            let events = collect(events).await.unwrap();
+            let removals: Vec<_> = events.iter().filter(|event| event.is_rm).collect();
+            assert_eq!(removals.len(), 1);
+            assert_eq!(removals[0].context_id, b"removed");
+        })
+    }
+
+    #[test]
+    fn test_sync_head_check() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider().with_extended_head(true);
+            let publisher = provider.keys.read().await.peer_id();
+            let url = serve(crate::tests::test_app(provider.clone())).await;
+            let client = ProviderClient::new(&url, &url).unwrap();
+            publish(&client, "extended", 1).await;
+
+            let check = HeadCheck {
+                max_age: Some(Duration::from_secs(60)),
+                require_extended: true,
+                ..HeadCheck::default()
+            };
+            let sync = SyncClient::new(&url)
+                .unwrap()
+                .with_publisher(publisher)
+                .with_head_check(check.clone());
+            let head = *provider.head.read().await;
+            assert_eq!(
+                sync.head().await.unwrap(),
+                head.map(|head| (publisher, head))
+            );
+
+            // A seq below the last one seen is a replay.
+            let seq = provider.head_seq.load(Ordering::SeqCst);
+            let replayed = SyncClient::new(&url).unwrap().with_head_check(HeadCheck {
+                last_seq: Some(seq + 1),
+                ..check.clone()
+            });
+            assert!(matches!(
+                replayed.head().await,
+                Err(SyncError::Head(SignedHeadError::Replayed { .. }))
+            ));
+
+            // Heads are signed as they are served, so a stale one can only come from something
+            // replaying an old response, like a cache.
+            let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+            let freshness = Freshness {
+                seq,
+                timestamp: hour_ago.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            };
+            let signer = provider.keys.read().await.signer();
+            let stale = SignedHead::sign(&*signer, head.unwrap(), Some(freshness))
+                .await
+                .unwrap();
+            let mut cache = tide::with_state(serde_json::to_value(&stale).unwrap());
+            cache
+                .at("/head")
+                .get(
+                    |r: tide::Request<serde_json::Value>| async move { Body::from_json(r.state()) },
+                );
+            let cache_url = serve(cache).await;
+            let sync = SyncClient::new(&cache_url)
+                .unwrap()
+                .with_publisher(publisher)
+                .with_head_check(check.clone());
+            assert!(matches!(
+                sync.head().await,
+                Err(SyncError::Head(SignedHeadError::Stale(age))) if age >= Duration::from_secs(3600)
+            ));
+            let sync = sync.with_head_check(HeadCheck {
+                max_age: None,
+                ..check.clone()
+            });
+            assert_eq!(
+                sync.head().await.unwrap(),
+                head.map(|head| (publisher, head))
+            );
+
+            // A legacy head can't be checked for either.
+            let url = serve(crate::tests::test_app(crate::tests::test_provider())).await;
+            let client = ProviderClient::new(&url, &url).unwrap();
+            publish(&client, "legacy", 1).await;
+            let sync = SyncClient::new(&url).unwrap().with_head_check(check);
+            assert!(matches!(
+                sync.head().await,
+                Err(SyncError::Head(SignedHeadError::NotExtended))
+            ));
+        })
+    }
+
+    #[test]
+    fn test_sync_rejects_bad_signature() {
+        async_std::task::block_on(async {
+            let provider = crate::tests::test_provider();
+            let url = serve(crate::tests::test_app(provider.clone())).await;
+            let client = ProviderClient::new(&url, &url).unwrap();
+            publish(&client, "signed", 1).await;
+            publish(&client, "forged", 1).await;
+
+            // Replace the head with an ad whose signature no longer covers its fields, stored
+            // under its own cid so only the signature check catches it.
+            let forged = {
+                let head = provider.head.read().await.unwrap();
+                let bs = provider.blockstore.read().await;
+                let mut ad: Advertisement = bs.get(&head).unwrap().unwrap();
+                ad.Addresses = vec!["/ip4/10.0.0.1/tcp/9999".into()];
+                bs.put(&ad, forest_cid::Code::Blake2b256).unwrap()
+            };
+            *provider.head.write().await = Some(forged);
+
+            let sync = SyncClient::new(&url).unwrap();
+            assert!(matches!(
+                sync.sync(None).await.map(|_| ()),
+                Err(SyncError::Chain(ChainError::BadSignature(cid, _))) if cid == forged
+            ));
+
+            // A block that doesn't match its cid is caught before decoding.
+            let bs = provider.blockstore.read().await;
+            let chunk = forest_encoding::to_vec(&EntryChunk {
+                Entries: vec![],
+                Next: None,
+            })
+            .unwrap();
+            bs.write(forged.to_bytes(), chunk).unwrap();
+            drop(bs);
+            assert!(matches!(
+                sync.sync(None).await.map(|_| ()),
+                Err(SyncError::Block(cid, BlockError::DigestMismatch)) if cid == forged
+            ));
+        })
+    }
+}
@@ -0,0 +1,276 @@
+//! TLS for the public and admin listeners. Certificates are read from PEM files and reloaded
+//! when the files change, so they can be renewed without restarting the provider.
+use async_std::net::TcpListener;
+use async_std::stream::StreamExt;
+use futures_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use futures_rustls::rustls::sign::{any_supported_type, CertifiedKey};
+use futures_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use futures_rustls::TlsAcceptor;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tracing::{debug, error, info, warn};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM file with the private key, PKCS#8, PKCS#1 (RSA) or SEC1 (EC).
+    pub key_path: PathBuf,
+    /// How often to check the files for changes.
+    #[serde(default = "default_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+}
+
+fn default_reload_interval_secs() -> u64 {
+    30
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("Failed to read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid PEM in {0}")]
+    InvalidPem(PathBuf),
+    #[error("No certificates found in {0}")]
+    NoCertificates(PathBuf),
+    #[error("No private key found in {0}")]
+    NoPrivateKey(PathBuf),
+    #[error("Unsupported private key in {0}")]
+    UnsupportedKey(PathBuf),
+}
+
+impl From<TlsError> for std::io::Error {
+    fn from(e: TlsError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Returns the label and decoded contents of each `-----BEGIN <label>-----` block.
+pub(crate) fn pem_blocks(pem: &str) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut blocks = vec![];
+    let mut current: Option<(String, String)> = None;
+    for line in pem.lines().map(str::trim) {
+        match current.take() {
+            None => {
+                if let Some(label) = line
+                    .strip_prefix("-----BEGIN ")
+                    .and_then(|l| l.strip_suffix("-----"))
+                {
+                    current = Some((label.to_string(), String::new()));
+                }
+            }
+            Some((label, mut body)) => {
+                if line == format!("-----END {}-----", label) {
+                    blocks.push((label, base64::decode(&body).ok()?));
+                } else {
+                    body.push_str(line);
+                    current = Some((label, body));
+                }
+            }
+        }
+    }
+    match current {
+        Some(_) => None,
+        None => Some(blocks),
+    }
+}
+
+pub(crate) fn read_pem(path: &Path) -> Result<Vec<(String, Vec<u8>)>, TlsError> {
+    let pem = std::fs::read_to_string(path).map_err(|e| TlsError::Io(path.into(), e))?;
+    pem_blocks(&pem).ok_or_else(|| TlsError::InvalidPem(path.into()))
+}
+
+fn load_certified_key(config: &TlsConfig) -> Result<CertifiedKey, TlsError> {
+    let certs: Vec<Certificate> = read_pem(&config.cert_path)?
+        .into_iter()
+        .filter(|(label, _)| label == "CERTIFICATE")
+        .map(|(_, der)| Certificate(der))
+        .collect();
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(config.cert_path.clone()));
+    }
+
+    let key = read_pem(&config.key_path)?
+        .into_iter()
+        .find(|(label, _)| {
+            matches!(
+                label.as_str(),
+                "PRIVATE KEY" | "RSA PRIVATE KEY" | "EC PRIVATE KEY"
+            )
+        })
+        .map(|(_, der)| PrivateKey(der))
+        .ok_or_else(|| TlsError::NoPrivateKey(config.key_path.clone()))?;
+    let key =
+        any_supported_type(&key).map_err(|_| TlsError::UnsupportedKey(config.key_path.clone()))?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn modified(config: &TlsConfig) -> Modified {
+    let cert = std::fs::metadata(&config.cert_path).ok()?.modified().ok()?;
+    let key = std::fs::metadata(&config.key_path).ok()?.modified().ok()?;
+    Some((cert, key))
+}
+
+/// Modification times of the certificate and key files.
+type Modified = Option<(SystemTime, SystemTime)>;
+
+/// Serves the most recently loaded certificate to every client.
+struct ReloadingResolver {
+    config: TlsConfig,
+    current: RwLock<(Modified, Arc<CertifiedKey>)>,
+}
+
+impl ReloadingResolver {
+    fn new(config: TlsConfig) -> Result<Self, TlsError> {
+        let modified = modified(&config);
+        let key = load_certified_key(&config)?;
+        Ok(ReloadingResolver {
+            config,
+            current: RwLock::new((modified, Arc::new(key))),
+        })
+    }
+
+    /// Reloads the certificate if either file changed since it was last loaded. A bad file is
+    /// logged and the previous certificate kept, so a half-written renewal doesn't take the
+    /// listener down.
+    fn reload_if_changed(&self) -> bool {
+        let modified = modified(&self.config);
+        if modified.is_none() || modified == self.current.read().unwrap().0 {
+            return false;
+        }
+        match load_certified_key(&self.config) {
+            Ok(key) => {
+                *self.current.write().unwrap() = (modified, Arc::new(key));
+                info!(
+                    cert_path = %self.config.cert_path.display(),
+                    "Reloaded TLS certificate"
+                );
+                true
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to reload TLS certificate, keeping the old one");
+                false
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().1.clone())
+    }
+}
+
+/// Like `tide::Server::listen`, but terminates TLS on each connection first.
+pub async fn listen<State: Clone + Send + Sync + 'static>(
+    app: tide::Server<State>,
+    addr: String,
+    config: TlsConfig,
+) -> std::io::Result<()> {
+    let interval = Duration::from_secs(config.reload_interval_secs);
+    let resolver = Arc::new(ReloadingResolver::new(config)?);
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+    async_std::task::spawn(async move {
+        loop {
+            async_std::task::sleep(interval).await;
+            resolver.reload_if_changed();
+        }
+    });
+
+    let listener = TcpListener::bind(&addr).await?;
+    info!(%addr, "Server listening with TLS");
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(error = %e, "Failed to accept connection");
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        async_std::task::spawn(async move {
+            let peer_addr = stream.peer_addr().ok();
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!(error = %e, "TLS handshake failed");
+                    return;
+                }
+            };
+            // async-h1 needs a stream it can clone for reading and writing.
+            let stream = async_dup::Arc::new(async_dup::Mutex::new(stream));
+            let res = async_h1::accept(stream, |mut req| {
+                let app = app.clone();
+                async move {
+                    req.set_peer_addr(peer_addr);
+                    app.respond(req).await
+                }
+            })
+            .await;
+            if let Err(e) = res {
+                debug!(error = %e, "Connection closed with error");
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(name)
+    }
+
+    #[test]
+    fn test_pem_blocks() {
+        let blocks = pem_blocks("junk\n-----BEGIN A-----\naGVs\nbG8=\n-----END A-----\n").unwrap();
+        assert_eq!(blocks, vec![("A".to_string(), b"hello".to_vec())]);
+        assert!(pem_blocks("-----BEGIN A-----\naGVs\n").is_none());
+        assert!(pem_blocks("-----BEGIN A-----\n!!\n-----END A-----\n").is_none());
+    }
+
+    #[test]
+    fn test_reload_on_change() {
+        let dir = std::env::temp_dir().join(format!("tls-test-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            reload_interval_secs: 1,
+        };
+        std::fs::copy(testdata("cert1.pem"), &config.cert_path).unwrap();
+        std::fs::copy(testdata("key1.pem"), &config.key_path).unwrap();
+
+        let resolver = ReloadingResolver::new(config.clone()).unwrap();
+        let first = resolver.current.read().unwrap().1.cert.clone();
+        assert!(!resolver.reload_if_changed());
+
+        // A key that can't be parsed keeps the old certificate.
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(&config.key_path, "not a key").unwrap();
+        assert!(!resolver.reload_if_changed());
+        assert_eq!(resolver.current.read().unwrap().1.cert, first);
+
+        std::fs::copy(testdata("cert2.pem"), &config.cert_path).unwrap();
+        std::fs::copy(testdata("key2.pem"), &config.key_path).unwrap();
+        assert!(resolver.reload_if_changed());
+        assert_ne!(resolver.current.read().unwrap().1.cert, first);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! The client side of HTTP, shared by the provider and sync clients, the remote signer and the
+//! operator commands. Each request goes out on a new connection: plain TCP for `http` urls, TLS for
+//! `https`, or a Unix socket for `http+unix:///path/to/socket`.
+use crate::tls::{self, TlsError};
+use async_std::net::TcpStream;
+use futures_rustls::rustls::{
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use futures_rustls::TlsConnector;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+use tide::http::{Request, Response, Url};
+
+#[derive(Error, Debug)]
+pub enum TransportError {
+    #[error("Invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("Failed to load CA certificates: {0}")]
+    Ca(#[from] TlsError),
+    #[error("Failed to connect: {0}")]
+    Connect(std::io::Error),
+    #[error("Request failed: {0}")]
+    Http(String),
+    #[error("Server returned {0}: {1}")]
+    Status(u16, String),
+}
+
+/// Where requests go. Paths are joined onto the base url, so a tenant's chain is reached with a
+/// base ending in `/p/<peer id>`.
+#[derive(Clone)]
+pub struct Endpoint {
+    base: Url,
+    via: Via,
+}
+
+#[derive(Clone)]
+enum Via {
+    Tcp,
+    Tls(TlsConnector),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl fmt::Debug for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let via = match &self.via {
+            Via::Tcp => "tcp".to_string(),
+            Via::Tls(_) => "tls".to_string(),
+            #[cfg(unix)]
+            Via::Unix(path) => path.display().to_string(),
+        };
+        f.debug_struct("Endpoint")
+            .field("base", &self.base.as_str())
+            .field("via", &via)
+            .finish()
+    }
+}
+
+impl Endpoint {
+    /// Takes `http://host:port`, `https://host:port` or `http+unix:///path/to/socket`, optionally
+    /// followed by a base path. A socket path can't be told apart from a base path, so requests
+    /// over a socket go to the paths as given.
+    pub fn new(url: &str) -> Result<Self, TransportError> {
+        if let Some(path) = url.strip_prefix("http+unix://") {
+            #[cfg(unix)]
+            return Ok(Endpoint {
+                // The host is only used for the Host header.
+                base: Url::parse("http://localhost/").unwrap(),
+                via: Via::Unix(path.into()),
+            });
+            #[cfg(not(unix))]
+            return Err(TransportError::InvalidUrl(format!(
+                "Unix sockets are not supported here: {}",
+                path
+            )));
+        }
+        // Without the trailing slash `join` would replace the last segment, e.g. a tenant's peer id.
+        let base = format!("{}/", url.trim_end_matches('/'));
+        let base = Url::parse(&base).map_err(|e| TransportError::InvalidUrl(e.to_string()))?;
+        let via = match base.scheme() {
+            "http" => Via::Tcp,
+            "https" => Via::Tls(connector(None)?),
+            scheme => {
+                return Err(TransportError::InvalidUrl(format!(
+                    "Unsupported scheme {}",
+                    scheme
+                )))
+            }
+        };
+        Ok(Endpoint { base, via })
+    }
+
+    /// Also trusts the CA certificates in the PEM file at `path`, for servers with a certificate
+    /// from a private CA. Only `https` endpoints are affected.
+    pub fn with_ca(self, path: &Path) -> Result<Self, TransportError> {
+        let via = match self.via {
+            Via::Tls(_) => Via::Tls(connector(Some(path))?),
+            via => via,
+        };
+        Ok(Endpoint { via, ..self })
+    }
+
+    pub fn url(&self, path: &str) -> Result<Url, TransportError> {
+        self.base
+            .join(path)
+            .map_err(|e| TransportError::InvalidUrl(e.to_string()))
+    }
+
+    /// Sends the request on a new connection. Responses other than 2xx are errors carrying the
+    /// body.
+    pub async fn send(&self, req: Request) -> Result<Response, TransportError> {
+        let res = match &self.via {
+            Via::Tcp => async_h1::connect(tcp(req.url()).await?, req).await,
+            Via::Tls(connector) => {
+                let host = req.url().host_str().unwrap_or_default();
+                let name = ServerName::try_from(host)
+                    .map_err(|_| TransportError::InvalidUrl(format!("Invalid host {}", host)))?;
+                let stream = connector
+                    .connect(name, tcp(req.url()).await?)
+                    .await
+                    .map_err(TransportError::Connect)?;
+                async_h1::connect(stream, req).await
+            }
+            #[cfg(unix)]
+            Via::Unix(path) => {
+                let stream = async_std::os::unix::net::UnixStream::connect(path)
+                    .await
+                    .map_err(TransportError::Connect)?;
+                async_h1::connect(stream, req).await
+            }
+        };
+        let mut res = res.map_err(|e| TransportError::Http(e.to_string()))?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let msg = res.body_string().await.unwrap_or_default();
+            return Err(TransportError::Status(status.into(), msg));
+        }
+        Ok(res)
+    }
+}
+
+async fn tcp(url: &Url) -> Result<TcpStream, TransportError> {
+    let addrs = url.socket_addrs(|| None).map_err(TransportError::Connect)?;
+    TcpStream::connect(&*addrs)
+        .await
+        .map_err(TransportError::Connect)
+}
+
+/// Trusts the Mozilla roots, plus the certificates in `ca_path`.
+fn connector(ca_path: Option<&Path>) -> Result<TlsConnector, TransportError> {
+    let mut roots = RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    if let Some(path) = ca_path {
+        let certs: Vec<Vec<u8>> = tls::read_pem(path)?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| der)
+            .collect();
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificates(path.into()).into());
+        }
+        for der in certs {
+            roots
+                .add(&Certificate(der))
+                .map_err(|_| TlsError::InvalidPem(path.into()))?;
+        }
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::prelude::*;
+    use tide::http::Method;
+
+    fn app() -> tide::Server<()> {
+        let mut app = tide::new();
+        app.at("/p/:id/hello")
+            .get(|r: tide::Request<()>| async move { Ok(format!("hello {}", r.param("id")?)) });
+        app
+    }
+
+    #[test]
+    fn test_tcp() {
+        async_std::task::block_on(async {
+            let url = crate::signer::tests::serve(app()).await;
+            let endpoint = Endpoint::new(&format!("{}/p/a", url)).unwrap();
+            let req = Request::new(Method::Get, endpoint.url("hello").unwrap());
+            let mut res = endpoint.send(req).await.unwrap();
+            assert_eq!(res.body_string().await.unwrap(), "hello a");
+
+            let req = Request::new(Method::Get, endpoint.url("missing").unwrap());
+            assert!(matches!(
+                endpoint.send(req).await,
+                Err(TransportError::Status(404, _))
+            ));
+            assert!(matches!(
+                Endpoint::new("ftp://127.0.0.1"),
+                Err(TransportError::InvalidUrl(_))
+            ));
+        })
+    }
+
+    #[test]
+    fn test_tls() {
+        async_std::task::block_on(async {
+            let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+            let port = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let config = tls::TlsConfig {
+                cert_path: testdata.join("server.pem"),
+                key_path: testdata.join("server-key.pem"),
+                reload_interval_secs: 30,
+            };
+            async_std::task::spawn(tls::listen(app(), format!("127.0.0.1:{}", port), config));
+            // The test certificate is for localhost.
+            let url = format!("https://localhost:{}/p/b", port);
+
+            // Issued by a test CA, so only trusted once the CA is given.
+            let endpoint = Endpoint::new(&url).unwrap();
+            let mut res = Err(TransportError::Http("not sent".into()));
+            for _ in 0..50 {
+                let req = Request::new(Method::Get, endpoint.url("hello").unwrap());
+                res = endpoint.send(req).await;
+                match &res {
+                    Err(TransportError::Connect(e))
+                        if e.kind() == std::io::ErrorKind::ConnectionRefused =>
+                    {
+                        async_std::task::sleep(std::time::Duration::from_millis(20)).await
+                    }
+                    _ => break,
+                }
+            }
+            assert!(matches!(res, Err(TransportError::Connect(_))));
+
+            let endpoint = endpoint.with_ca(&testdata.join("ca.pem")).unwrap();
+            let req = Request::new(Method::Get, endpoint.url("hello").unwrap());
+            let mut res = endpoint.send(req).await.unwrap();
+            assert_eq!(res.body_string().await.unwrap(), "hello b");
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        async_std::task::block_on(async {
+            let path =
+                std::env::temp_dir().join(format!("transport-{:016x}.sock", rand::random::<u64>()));
+            let listener = async_std::os::unix::net::UnixListener::bind(&path)
+                .await
+                .unwrap();
+            let app = app();
+            async_std::task::spawn(async move {
+                let mut incoming = listener.incoming();
+                while let Some(Ok(stream)) = incoming.next().await {
+                    let app = app.clone();
+                    async_std::task::spawn(async move {
+                        let _ = async_h1::accept(stream, |req| app.respond(req)).await;
+                    });
+                }
+            });
+
+            let endpoint = Endpoint::new(&format!("http+unix://{}", path.display())).unwrap();
+            let req = Request::new(Method::Get, endpoint.url("/p/c/hello").unwrap());
+            let mut res = endpoint.send(req).await.unwrap();
+            assert_eq!(res.body_string().await.unwrap(), "hello c");
+            std::fs::remove_file(&path).unwrap();
+        })
+    }
+}
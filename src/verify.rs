@@ -0,0 +1,203 @@
+use crate::advertisement::{AdSigError, Advertisement};
+use crate::chain;
+use forest_cid::Cid;
+use forest_ipld::Ipld;
+use ipld_blockstore::BlockStore;
+use multihash::MultihashDigest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BlockError {
+    #[error("Unsupported codec {0:#x}")]
+    UnsupportedCodec(u64),
+    #[error("Unsupported multihash code {0:#x}")]
+    UnsupportedHash(u64),
+    #[error("Digest does not match the cid")]
+    DigestMismatch,
+    #[error("Block is not valid dag-cbor: {0}")]
+    InvalidCbor(String),
+    #[error("Bad advertisement signature: {0}")]
+    BadSignature(AdSigError),
+}
+
+/// Checks that the bytes hash to the multihash in the cid.
+pub(crate) fn digest_matches(cid: &Cid, bytes: &[u8]) -> Result<(), BlockError> {
+    let mh = cid.hash();
+    let code =
+        multihash::Code::try_from(mh.code()).map_err(|_| BlockError::UnsupportedHash(mh.code()))?;
+    if code.digest(bytes).to_bytes() != mh.to_bytes() {
+        return Err(BlockError::DigestMismatch);
+    }
+    Ok(())
+}
+
+/// Checks that the bytes are what the cid claims: the digest matches, and the block decodes with
+/// the cid's codec. Everything in the chain is dag-cbor.
+pub(crate) fn verify_block(cid: &Cid, bytes: &[u8]) -> Result<(), BlockError> {
+    if cid.codec() != forest_cid::DAG_CBOR {
+        return Err(BlockError::UnsupportedCodec(cid.codec()));
+    }
+    digest_matches(cid, bytes)?;
+    forest_encoding::from_slice::<Ipld>(bytes)
+        .map_err(|e| BlockError::InvalidCbor(format!("{}", e)))?;
+    Ok(())
+}
+
+/// Checks the signature of a block that decodes as an advertisement. Other blocks, such as entry
+/// chunks, pass as they are.
+pub(crate) fn verify_ad_block(bytes: &[u8]) -> Result<(), BlockError> {
+    match forest_encoding::from_slice::<Advertisement>(bytes) {
+        Ok(ad) => ad.verify_sig().map_err(BlockError::BadSignature),
+        Err(_) => Ok(()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FsckReport {
+    /// Number of blocks that were checked.
+    pub checked: usize,
+    /// One message per problem found.
+    pub errors: Vec<String>,
+    /// The first ad, walking back from the head, whose signature doesn't verify.
+    #[serde(default)]
+    pub first_bad_signature: Option<String>,
+}
+
+fn check_block<BS: BlockStore>(bs: &BS, cid: Cid, report: &mut FsckReport) {
+    report.checked += 1;
+    match bs.get_bytes(&cid) {
+        Ok(Some(bytes)) => {
+            if let Err(e) = verify_block(&cid, &bytes) {
+                report.errors.push(format!("Block {}: {}", cid, e));
+            }
+        }
+        Ok(None) => report.errors.push(format!("Block {}: missing", cid)),
+        Err(e) => report.errors.push(format!("Block {}: {}", cid, e)),
+    }
+}
+
+/// Verifies every ad and entry chunk reachable from the head, except for pruned entries, and the
+/// signature of every ad.
+pub(crate) fn fsck<BS: BlockStore>(
+    bs: &BS,
+    head: Option<Cid>,
+    pruned: &HashSet<Cid>,
+) -> FsckReport {
+    let mut report = FsckReport::default();
+    for res in chain::ads(bs, head) {
+        let (cid, ad) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                // Without the ad we can't find the rest of the chain.
+                report.errors.push(format!("{}", e));
+                break;
+            }
+        };
+        check_block(bs, cid, &mut report);
+        if let Err(e) = ad.verify_sig() {
+            report
+                .errors
+                .push(format!("Block {}: {}", cid, BlockError::BadSignature(e)));
+            report.first_bad_signature.get_or_insert(cid.to_string());
+        }
+        match chain::retained_entry_chunks(bs, cid, &ad, pruned) {
+            Ok(chunks) => chunks
+                .into_iter()
+                .for_each(|(chunk_cid, _)| check_block(bs, chunk_cid, &mut report)),
+            Err(e) => report.errors.push(format!("{}", e)),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forest_db::{MemoryDB, Store};
+
+    #[test]
+    fn test_verify_block() {
+        let bs = MemoryDB::default();
+        let cid = bs
+            .put(&Ipld::String("hello".into()), forest_cid::Code::Blake2b256)
+            .unwrap();
+        let bytes = bs.get_bytes(&cid).unwrap().unwrap();
+        verify_block(&cid, &bytes).expect("valid block failed verification");
+
+        let other = forest_encoding::to_vec(&Ipld::String("other".into())).unwrap();
+        assert!(matches!(
+            verify_block(&cid, &other),
+            Err(BlockError::DigestMismatch)
+        ));
+
+        let raw = forest_cid::Cid::new_v1(forest_cid::RAW, *cid.hash());
+        assert!(matches!(
+            verify_block(&raw, &bytes),
+            Err(BlockError::UnsupportedCodec(_))
+        ));
+    }
+
+    #[test]
+    fn test_fsck_reports_corruption() {
+        let bs = MemoryDB::default();
+        let first = crate::chain::tests::publish(&bs, None, "first");
+        let head = crate::chain::tests::publish(&bs, Some(first), "fsck");
+        let ad: Advertisement = bs.get(&head).unwrap().unwrap();
+        let chunk = chain::link(&ad.Entries, head).unwrap().unwrap();
+
+        let report = fsck(&bs, Some(head), &HashSet::new());
+        // Each ad links two single entry chunks.
+        assert_eq!(report.checked, 6);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.first_bad_signature, None);
+
+        // Overwrite the chunk with bytes that still decode, but hash differently.
+        let other = forest_encoding::to_vec(&crate::advertisement::EntryChunk {
+            Entries: vec![Ipld::Bytes(vec![1])],
+            Next: None,
+        })
+        .unwrap();
+        bs.write(chunk.to_bytes(), other).unwrap();
+        let report = fsck(&bs, Some(head), &HashSet::new());
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.first_bad_signature, None);
+
+        // Ads whose fields were changed after signing, stored under their own cids so only the
+        // signature check catches them. The newest is reported first.
+        let mut forged: Advertisement = bs.get(&first).unwrap().unwrap();
+        forged.Addresses = vec!["/ip4/10.0.0.1/tcp/9999".into()];
+        let forged_first = bs.put(&forged, forest_cid::Code::Blake2b256).unwrap();
+        let mut forged: Advertisement = bs.get(&head).unwrap().unwrap();
+        forged.PreviousID = Some(Ipld::Link(forged_first));
+        let forged_head = bs.put(&forged, forest_cid::Code::Blake2b256).unwrap();
+        let report = fsck(&bs, Some(forged_head), &HashSet::new());
+        assert_eq!(report.errors.len(), 3);
+        assert_eq!(report.first_bad_signature, Some(forged_head.to_string()));
+    }
+
+    #[test]
+    fn test_verify_ad_block() {
+        let bs = MemoryDB::default();
+        let first = crate::chain::tests::publish(&bs, None, "first");
+        let second = crate::chain::tests::publish(&bs, Some(first), "second");
+        let bytes = bs.get_bytes(&second).unwrap().unwrap();
+        verify_ad_block(&bytes).expect("valid ad failed verification");
+
+        // An ad whose fields were changed after signing.
+        let mut ad: Advertisement = bs.get(&second).unwrap().unwrap();
+        ad.PreviousID = Some(Ipld::Link(second));
+        let forged = bs.put(&ad, forest_cid::Code::Blake2b256).unwrap();
+        let bytes = bs.get_bytes(&forged).unwrap().unwrap();
+        assert!(matches!(
+            verify_ad_block(&bytes),
+            Err(BlockError::BadSignature(_))
+        ));
+
+        // Entry chunks aren't ads, and have no signature to check.
+        let chunk = chain::link(&ad.Entries, second).unwrap().unwrap();
+        let bytes = bs.get_bytes(&chunk).unwrap().unwrap();
+        verify_ad_block(&bytes).expect("entry chunk failed verification");
+    }
+}